@@ -0,0 +1,156 @@
+use crate::math::Vec3;
+use crate::framebuffer::Framebuffer;
+use rayon::prelude::*;
+
+// Normalized 5-tap binomial kernel for the separable blur
+const BLUR_WEIGHTS: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+// HDR glow around everything brighter than `threshold`: the bright part of
+// the image is halved `levels` times, each level blurred, and the levels
+// summed back up and added over the image. Coarse levels give the wide
+// halo, fine ones keep it tight around the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bloom {
+    pub enabled: bool,
+    pub threshold: f32,
+    // Width of the soft transition around `threshold`
+    pub knee: f32,
+    pub intensity: f32,
+    pub levels: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl Bloom {
+    pub fn new() -> Self {
+        Bloom {
+            enabled: false,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.6,
+            levels: 4,
+        }
+    }
+
+    // Adds the glow over `framebuffer.display`
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        if !self.enabled || self.intensity <= 0.0 {
+            return;
+        }
+
+        let bright = Image {
+            width: framebuffer.width,
+            height: framebuffer.height,
+            pixels: framebuffer.display.par_iter().map(|color| self.bright_pass(*color)).collect(),
+        };
+
+        let mut chain = vec![bright.blur()];
+        for _ in 1..self.levels.max(1) {
+            let previous = &chain[chain.len() - 1];
+            if previous.width < 2 || previous.height < 2 {
+                break;
+            }
+            let next = previous.downsample().blur();
+            chain.push(next);
+        }
+
+        // Fold coarse levels into finer ones on the way back up
+        let mut glow = chain.pop().unwrap();
+        while let Some(finer) = chain.pop() {
+            glow = finer.add(&glow.upsample(finer.width, finer.height));
+        }
+
+        let scale = self.intensity / self.levels.max(1) as f32;
+        framebuffer
+            .display
+            .par_iter_mut()
+            .zip(glow.pixels.par_iter())
+            .for_each(|(color, glow)| *color = *color + *glow * scale);
+    }
+
+    // Part of `color` above the threshold, with a quadratic knee so the
+    // glow fades in instead of switching on
+    fn bright_pass(&self, color: Vec3) -> Vec3 {
+        let brightness = color.x.max(color.y).max(color.z);
+        if brightness <= 0.0 {
+            return Vec3::zero();
+        }
+
+        let knee = self.knee.max(1e-4);
+        let soft = (brightness - self.threshold + knee).clamp(0.0, 2.0 * knee);
+        let soft = soft * soft / (4.0 * knee);
+        let contribution = soft.max(brightness - self.threshold) / brightness;
+        color * contribution.max(0.0)
+    }
+}
+
+impl Image {
+    fn at(&self, x: isize, y: isize) -> Vec3 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    // Half resolution, each pixel the average of the 2x2 block under it
+    fn downsample(&self) -> Image {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let pixels = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = ((index % width) as isize * 2, (index / width) as isize * 2);
+                (self.at(x, y) + self.at(x + 1, y) + self.at(x, y + 1) + self.at(x + 1, y + 1)) * 0.25
+            })
+            .collect();
+        Image { width, height, pixels }
+    }
+
+    // Bilinear resize to `width` by `height`
+    fn upsample(&self, width: usize, height: usize) -> Image {
+        let (scale_x, scale_y) = (self.width as f32 / width as f32, self.height as f32 / height as f32);
+        let pixels = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let u = ((index % width) as f32 + 0.5) * scale_x - 0.5;
+                let v = ((index / width) as f32 + 0.5) * scale_y - 0.5;
+                let (x0, y0) = (u.floor(), v.floor());
+                let (fx, fy) = (u - x0, v - y0);
+                let (x0, y0) = (x0 as isize, y0 as isize);
+
+                let top = self.at(x0, y0).lerp(&self.at(x0 + 1, y0), fx);
+                let bottom = self.at(x0, y0 + 1).lerp(&self.at(x0 + 1, y0 + 1), fx);
+                top.lerp(&bottom, fy)
+            })
+            .collect();
+        Image { width, height, pixels }
+    }
+
+    fn blur(&self) -> Image {
+        self.convolve(1, 0).convolve(0, 1)
+    }
+
+    // One pass of the binomial kernel along (step_x, step_y)
+    fn convolve(&self, step_x: isize, step_y: isize) -> Image {
+        let width = self.width;
+        let pixels = (0..self.width * self.height)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = ((index % width) as isize, (index / width) as isize);
+                BLUR_WEIGHTS.iter().enumerate().fold(Vec3::zero(), |sum, (tap, weight)| {
+                    let offset = tap as isize - 2;
+                    sum + self.at(x + offset * step_x, y + offset * step_y) * *weight
+                })
+            })
+            .collect();
+        Image { width: self.width, height: self.height, pixels }
+    }
+
+    fn add(&self, other: &Image) -> Image {
+        let pixels = self.pixels.iter().zip(&other.pixels).map(|(a, b)| *a + *b).collect();
+        Image { width: self.width, height: self.height, pixels }
+    }
+}
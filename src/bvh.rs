@@ -0,0 +1,403 @@
+use crate::math::{Vec3, Ray};
+use crate::scene::{Cube, Sphere, HitInfo};
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+// Deepest node `subdivide` creates. Traversal keeps at most one entry per
+// level on its fixed `TRAVERSAL_STACK_SIZE` stack, plus one.
+const MAX_DEPTH: usize = 48;
+const TRAVERSAL_STACK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_cube(cube: &Cube) -> Self {
+        Aabb {
+            min: cube.center - cube.size * 0.5,
+            max: cube.center + cube.size * 0.5,
+        }
+    }
+
+    pub fn from_sphere(sphere: &Sphere) -> Self {
+        let extent = Vec3::new(sphere.radius, sphere.radius, sphere.radius);
+        Aabb {
+            min: sphere.center - extent,
+            max: sphere.center + extent,
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Vec3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn grow(&self, point: &Vec3) -> Aabb {
+        Aabb {
+            min: Vec3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z)),
+            max: Vec3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z)),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = self.max - self.min;
+        if extent.x < 0.0 || extent.y < 0.0 || extent.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    // Slab test, returns the entry distance if the box is hit before t_max
+    pub fn hit(&self, ray: &Ray, inv_dir: &Vec3, t_max: f32) -> Option<f32> {
+        let t1 = (self.min.x - ray.origin.x) * inv_dir.x;
+        let t2 = (self.max.x - ray.origin.x) * inv_dir.x;
+        let t3 = (self.min.y - ray.origin.y) * inv_dir.y;
+        let t4 = (self.max.y - ray.origin.y) * inv_dir.y;
+        let t5 = (self.min.z - ray.origin.z) * inv_dir.z;
+        let t6 = (self.max.z - ray.origin.z) * inv_dir.z;
+
+        let tmin = t1.min(t2).max(t3.min(t4)).max(t5.min(t6)).max(0.0);
+        let tmax = t1.max(t2).min(t3.max(t4)).min(t5.max(t6)).min(t_max);
+
+        if tmin <= tmax {
+            Some(tmin)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Primitive {
+    Cube(usize),
+    Sphere(usize),
+}
+
+impl Primitive {
+    pub fn bounds(&self, cubes: &[Cube], spheres: &[Sphere]) -> Aabb {
+        match *self {
+            Primitive::Cube(index) => Aabb::from_cube(&cubes[index]),
+            Primitive::Sphere(index) => Aabb::from_sphere(&spheres[index]),
+        }
+    }
+}
+
+// Interior nodes store the index of their left child in `first` (the right
+// child always follows it), leaves store a range into `primitives`.
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    first: usize,
+    count: usize,
+}
+
+impl BvhNode {
+    fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    primitives: Vec<Primitive>,
+}
+
+impl Bvh {
+    pub fn build(cubes: &[Cube], spheres: &[Sphere]) -> Self {
+        let mut primitives: Vec<Primitive> = (0..cubes.len()).map(Primitive::Cube).collect();
+        primitives.extend((0..spheres.len()).map(Primitive::Sphere));
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(primitives.len() * 2),
+            primitives,
+        };
+
+        if bvh.primitives.is_empty() {
+            return bvh;
+        }
+
+        let bounds: Vec<Aabb> = bvh.primitives
+            .iter()
+            .map(|primitive| primitive.bounds(cubes, spheres))
+            .collect();
+        let mut order: Vec<usize> = (0..bvh.primitives.len()).collect();
+
+        bvh.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: order.len() });
+        bvh.subdivide(0, 0, &mut order, &bounds);
+
+        bvh.primitives = order.iter().map(|&i| bvh.primitives[i]).collect();
+        bvh
+    }
+
+    fn subdivide(&mut self, node_index: usize, depth: usize, order: &mut [usize], bounds: &[Aabb]) {
+        debug_assert!(depth <= MAX_DEPTH);
+        let first = self.nodes[node_index].first;
+        let count = self.nodes[node_index].count;
+        let items = &mut order[first..first + count];
+
+        let mut node_bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &i in items.iter() {
+            node_bounds = node_bounds.union(&bounds[i]);
+            centroid_bounds = centroid_bounds.grow(&bounds[i].centroid());
+        }
+        self.nodes[node_index].bounds = node_bounds;
+
+        // Degenerate splits on coincident centroids could otherwise nest
+        // deeper than the traversal stack
+        if count <= 2 || depth >= MAX_DEPTH {
+            return;
+        }
+
+        // Binned SAH: pick the axis and bin boundary with the lowest cost
+        let mut best_cost = f32::INFINITY;
+        let mut best_axis = 0;
+        let mut best_split = 0;
+
+        for axis in 0..3 {
+            let lo = axis_of(&centroid_bounds.min, axis);
+            let hi = axis_of(&centroid_bounds.max, axis);
+            if hi - lo <= f32::EPSILON {
+                continue;
+            }
+
+            let mut bins = [Bin { bounds: Aabb::empty(), count: 0 }; BIN_COUNT];
+            let scale = BIN_COUNT as f32 / (hi - lo);
+            for &i in items.iter() {
+                let b = bin_index(axis_of(&bounds[i].centroid(), axis), lo, scale);
+                bins[b].count += 1;
+                bins[b].bounds = bins[b].bounds.union(&bounds[i]);
+            }
+
+            let mut left_area = [0.0; BIN_COUNT - 1];
+            let mut left_count = [0; BIN_COUNT - 1];
+            let mut left_box = Aabb::empty();
+            let mut left_sum = 0;
+            for split in 0..BIN_COUNT - 1 {
+                left_sum += bins[split].count;
+                left_box = left_box.union(&bins[split].bounds);
+                left_count[split] = left_sum;
+                left_area[split] = left_box.surface_area();
+            }
+
+            let mut right_box = Aabb::empty();
+            let mut right_sum = 0;
+            for split in (0..BIN_COUNT - 1).rev() {
+                right_sum += bins[split + 1].count;
+                right_box = right_box.union(&bins[split + 1].bounds);
+
+                let cost = left_count[split] as f32 * left_area[split]
+                    + right_sum as f32 * right_box.surface_area();
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_axis = axis;
+                    best_split = split;
+                }
+            }
+        }
+
+        let parent_area = node_bounds.surface_area().max(f32::EPSILON);
+        let split_cost = TRAVERSAL_COST + INTERSECTION_COST * best_cost / parent_area;
+        let leaf_cost = INTERSECTION_COST * count as f32;
+        if !best_cost.is_finite() || (split_cost >= leaf_cost && count <= MAX_LEAF_SIZE) {
+            return;
+        }
+
+        let lo = axis_of(&centroid_bounds.min, best_axis);
+        let scale = BIN_COUNT as f32 / (axis_of(&centroid_bounds.max, best_axis) - lo);
+
+        // In-place partition around the chosen bin boundary
+        let mut left_count = 0;
+        for j in 0..count {
+            let b = bin_index(axis_of(&bounds[items[j]].centroid(), best_axis), lo, scale);
+            if b <= best_split {
+                items.swap(j, left_count);
+                left_count += 1;
+            }
+        }
+
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let left_index = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first, count: left_count });
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: first + left_count, count: count - left_count });
+        self.nodes[node_index].first = left_index;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left_index, depth + 1, order, bounds);
+        self.subdivide(left_index + 1, depth + 1, order, bounds);
+    }
+
+    // Recomputes node bounds from `primitive_bounds` after primitives moved,
+    // keeping the topology. `primitive_bounds` may return swept bounds for
+    // primitives that move during the shutter. Children are always stored
+    // after their parent, so a reverse sweep visits them before the node
+    // that encloses them.
+    pub fn refit<F>(&mut self, primitive_bounds: F)
+    where
+        F: Fn(Primitive) -> Aabb,
+    {
+        for node_index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[node_index];
+            let bounds = if node.is_leaf() {
+                self.primitives[node.first..node.first + node.count]
+                    .iter()
+                    .fold(Aabb::empty(), |acc, &primitive| acc.union(&primitive_bounds(primitive)))
+            } else {
+                self.nodes[node.first].bounds.union(&self.nodes[node.first + 1].bounds)
+            };
+            self.nodes[node_index].bounds = bounds;
+        }
+    }
+
+    pub fn intersect<F>(&self, ray: &Ray, mut intersect_primitive: F) -> HitInfo
+    where
+        F: FnMut(Primitive) -> HitInfo,
+    {
+        let mut closest_hit = HitInfo::miss();
+        let mut closest_primitive = None;
+        if self.nodes.is_empty() {
+            return closest_hit;
+        }
+
+        let inv_dir = Vec3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut stack = [0usize; TRAVERSAL_STACK_SIZE];
+        let mut stack_size = 0;
+        let mut node_index = 0;
+
+        if self.nodes[0].bounds.hit(ray, &inv_dir, f32::INFINITY).is_none() {
+            return closest_hit;
+        }
+
+        loop {
+            let node = &self.nodes[node_index];
+
+            if node.is_leaf() {
+                for primitive in &self.primitives[node.first..node.first + node.count] {
+                    let hit = intersect_primitive(*primitive);
+                    // Ties on shared faces go to the primitive declared first
+                    let closer = hit.distance < closest_hit.distance
+                        || (hit.distance == closest_hit.distance && Some(*primitive) < closest_primitive);
+                    if hit.hit && closer {
+                        closest_hit = hit;
+                        closest_primitive = Some(*primitive);
+                    }
+                }
+            } else {
+                let left = node.first;
+                let right = node.first + 1;
+                let left_hit = self.nodes[left].bounds.hit(ray, &inv_dir, closest_hit.distance);
+                let right_hit = self.nodes[right].bounds.hit(ray, &inv_dir, closest_hit.distance);
+
+                // Visit the nearer child first so the far one can be culled
+                match (left_hit, right_hit) {
+                    (Some(t_left), Some(t_right)) => {
+                        let (near, far) = if t_left <= t_right { (left, right) } else { (right, left) };
+                        stack[stack_size] = far;
+                        stack_size += 1;
+                        node_index = near;
+                        continue;
+                    }
+                    (Some(_), None) => {
+                        node_index = left;
+                        continue;
+                    }
+                    (None, Some(_)) => {
+                        node_index = right;
+                        continue;
+                    }
+                    (None, None) => {}
+                }
+            }
+
+            // Pop until a node that can still contain a closer hit
+            loop {
+                if stack_size == 0 {
+                    return closest_hit;
+                }
+                stack_size -= 1;
+                let candidate = stack[stack_size];
+                if self.nodes[candidate].bounds.hit(ray, &inv_dir, closest_hit.distance).is_some() {
+                    node_index = candidate;
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn occluded<F>(&self, ray: &Ray, max_t: f32, mut hit_distance: F) -> bool
+    where
+        F: FnMut(Primitive) -> Option<f32>,
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_dir = Vec3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut stack = [0usize; TRAVERSAL_STACK_SIZE];
+        let mut stack_size = 1;
+
+        // Order does not matter for an any-hit query, so no near/far sorting
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size]];
+
+            if node.bounds.hit(ray, &inv_dir, max_t).is_none() {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for primitive in &self.primitives[node.first..node.first + node.count] {
+                    if matches!(hit_distance(*primitive), Some(t) if t <= max_t) {
+                        return true;
+                    }
+                }
+            } else {
+                stack[stack_size] = node.first;
+                stack[stack_size + 1] = node.first + 1;
+                stack_size += 2;
+            }
+        }
+
+        false
+    }
+}
+
+fn axis_of(v: &Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+fn bin_index(value: f32, lo: f32, scale: f32) -> usize {
+    (((value - lo) * scale) as usize).min(BIN_COUNT - 1)
+}
@@ -1,4 +1,5 @@
 use crate::math::{Vec3, Ray};
+use crate::sampling::concentric_disk;
 use std::f32::consts::PI;
 
 #[derive(Debug, Clone)]
@@ -7,8 +8,10 @@ pub struct Camera {
     pub target: Vec3,
     pub up: Vec3,
     pub fov: f32,
+    #[allow(dead_code)]
     pub aspect_ratio: f32,
     pub near: f32,
+    #[allow(dead_code)]
     pub far: f32,
     
     // Camera controls
@@ -16,13 +19,32 @@ pub struct Camera {
     pub pitch: f32,
     pub distance: f32,
     pub zoom: f32,
+
+    // Thin lens: diameter of the lens disk (0 is a pinhole) and distance
+    // along the view direction of the plane in perfect focus
+    pub aperture: f32,
+    pub focus_distance: f32,
+
+    // Shutter interval in seconds relative to the frame time; rays are
+    // spread over it so anything moving while it is open smears. Equal
+    // values give an instantaneous exposure.
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    // Units per second, measured between the last two frames
+    pub velocity: Vec3,
+    pub target_velocity: Vec3,
+    previous_position: Vec3,
+    previous_target: Vec3,
 }
 
 impl Camera {
     pub fn new(aspect_ratio: f32) -> Self {
+        let position = Vec3::new(0.0, 5.0, 10.0);
+        let target = Vec3::new(0.0, 0.0, 0.0);
+
         Camera {
-            position: Vec3::new(0.0, 5.0, 10.0),
-            target: Vec3::new(0.0, 0.0, 0.0),
+            position,
+            target,
             up: Vec3::new(0.0, 1.0, 0.0),
             fov: 45.0,
             aspect_ratio,
@@ -32,6 +54,14 @@ impl Camera {
             pitch: 0.3,
             distance: 15.0,
             zoom: 1.0,
+            aperture: 0.0,
+            focus_distance: 15.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            velocity: Vec3::zero(),
+            target_velocity: Vec3::zero(),
+            previous_position: position,
+            previous_target: target,
         }
     }
 
@@ -44,24 +74,91 @@ impl Camera {
     }
 
     pub fn get_ray(&self, x: f32, y: f32, width: f32, height: f32) -> Ray {
+        self.get_lens_ray(x, y, width, height, 0.5, 0.5, 0.0)
+    }
+
+    // Ray through image position `x`, `y` starting at the point of the lens
+    // disk given by `lens_u`, `lens_v` in [0, 1), taken `time` seconds away
+    // from the frame time. All rays through the same image position meet
+    // again on the focus plane.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_lens_ray(&self, x: f32, y: f32, width: f32, height: f32, lens_u: f32, lens_v: f32, time: f32) -> Ray {
+        // Extrapolate the pose from the last frame's motion
+        let position = self.position + self.velocity * time;
+        let target = self.target + self.target_velocity * time;
+
         let aspect = width / height;
         let fov_rad = (self.fov / self.zoom).to_radians();
         let half_height = (fov_rad / 2.0).tan();
         let half_width = aspect * half_height;
 
-        let w = (self.position - self.target).normalize();
+        let w = (position - target).normalize();
         let u = self.up.cross(&w).normalize();
         let v = w.cross(&u);
 
-        let lower_left_corner = self.position - half_width * u - half_height * v - w;
+        let lower_left_corner = position - half_width * u - half_height * v - w;
         let horizontal = 2.0 * half_width * u;
         let vertical = 2.0 * half_height * v;
 
         let s = x / width;
         let t = y / height;
 
-        let direction = lower_left_corner + s * horizontal + t * vertical - self.position;
-        Ray::new(self.position, direction)
+        let direction = lower_left_corner + s * horizontal + t * vertical - position;
+        if self.aperture <= 0.0 {
+            return Ray::with_time(position, direction, time);
+        }
+
+        // `direction` is one unit long along the view axis
+        let focus_point = position + direction * self.focus_distance;
+        let (disk_x, disk_y) = concentric_disk(lens_u, lens_v);
+        let lens_radius = self.aperture * 0.5;
+        let origin = position + u * (disk_x * lens_radius) + v * (disk_y * lens_radius);
+        Ray::with_time(origin, focus_point - origin, time)
+    }
+
+    // Image position, in the same units as `get_ray` takes, at which
+    // `point` appears through the pinhole at the frame time. None when it
+    // lies behind the near plane.
+    pub fn project(&self, point: &Vec3, width: f32, height: f32) -> Option<(f32, f32)> {
+        let aspect = width / height;
+        let fov_rad = (self.fov / self.zoom).to_radians();
+        let half_height = (fov_rad / 2.0).tan();
+        let half_width = aspect * half_height;
+
+        let w = (self.position - self.target).normalize();
+        let u = self.up.cross(&w).normalize();
+        let v = w.cross(&u);
+
+        let offset = *point - self.position;
+        let depth = -offset.dot(&w);
+        if depth < self.near {
+            return None;
+        }
+
+        let s = (offset.dot(&u) / (depth * half_width) + 1.0) * 0.5;
+        let t = (offset.dot(&v) / (depth * half_height) + 1.0) * 0.5;
+        Some((s * width, t * height))
+    }
+
+    // A time within the shutter interval for `u` in [0, 1)
+    pub fn shutter_time(&self, u: f32) -> f32 {
+        self.shutter_open + (self.shutter_close - self.shutter_open) * u
+    }
+
+    // Records how far the camera moved since the previous frame
+    pub fn update_motion(&mut self, delta_time: f32) {
+        if delta_time > 0.0 {
+            self.velocity = (self.position - self.previous_position) / delta_time;
+            self.target_velocity = (self.target - self.previous_target) / delta_time;
+        }
+        self.previous_position = self.position;
+        self.previous_target = self.target;
+    }
+
+    // Puts the focus plane through `point`
+    pub fn focus_on(&mut self, point: &Vec3) {
+        let forward = (self.target - self.position).normalize();
+        self.focus_distance = (*point - self.position).dot(&forward).max(self.near);
     }
 
     pub fn handle_mouse_input(&mut self, delta_x: f32, delta_y: f32, sensitivity: f32) {
@@ -71,6 +168,7 @@ impl Camera {
         self.update_position();
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn handle_keyboard_input(&mut self, forward: bool, backward: bool, left: bool, right: bool, up: bool, down: bool, speed: f32) {
         let w = (self.position - self.target).normalize();
         let u = self.up.cross(&w).normalize();
@@ -106,6 +204,7 @@ impl Camera {
         self.update_position();
     }
 
+    #[allow(dead_code)]
     pub fn orbit_around_target(&mut self, delta_time: f32, speed: f32) {
         self.yaw += delta_time * speed;
         self.update_position();
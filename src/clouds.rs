@@ -0,0 +1,170 @@
+use crate::math::{Vec3, Ray};
+use crate::sampling::hash;
+use crate::fog::henyey_greenstein;
+
+const CLOUD_STEPS: u32 = 16;
+const LIGHT_STEPS: u32 = 4;
+const OCTAVES: u32 = 4;
+// Longest stretch of a shadow ray inside the layer that gets marched, for
+// rays that run almost parallel to it
+const MAX_SHADOW_MARCH: f32 = 40.0;
+
+// A slab of fractal value noise between `bottom` and `top`, carried along
+// by the wind. Only the parts of the noise above `coverage` become cloud.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clouds {
+    pub enabled: bool,
+    pub bottom: f32,
+    pub top: f32,
+    // Fraction of the sky left clear, in [0, 1]
+    pub coverage: f32,
+    // Extinction per unit length of the densest cloud
+    pub density: f32,
+    // Size of the largest puffs, in world units
+    pub scale: f32,
+    // Drift in world units per second
+    pub wind: Vec3,
+}
+
+impl Clouds {
+    pub fn new() -> Self {
+        Clouds {
+            enabled: false,
+            bottom: 7.0,
+            top: 9.0,
+            coverage: 0.5,
+            density: 1.5,
+            scale: 6.0,
+            wind: Vec3::new(0.6, 0.0, 0.25),
+        }
+    }
+
+    // Cloud density at `point` at scene time `time`
+    pub fn density_at(&self, point: &Vec3, time: f32) -> f32 {
+        if point.y <= self.bottom || point.y >= self.top {
+            return 0.0;
+        }
+
+        let p = (*point - self.wind * time) / self.scale;
+        let mut noise = 0.0;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.0;
+        for _ in 0..OCTAVES {
+            noise += amplitude * value_noise(&(p * frequency));
+            amplitude *= 0.5;
+            frequency *= 2.03;
+        }
+
+        // Round off the top and bottom of the layer
+        let height = (point.y - self.bottom) / (self.top - self.bottom);
+        let profile = (height * (1.0 - height) * 4.0).min(1.0);
+        ((noise - self.coverage) / (1.0 - self.coverage)).max(0.0) * profile * self.density
+    }
+
+    // Sky seen along `direction` from the ground behind the clouds, lit by
+    // `lights` given as direction towards the light and its color
+    pub fn composite(&self, direction: &Vec3, background: Vec3, lights: &[(Vec3, Vec3)], ambient: Vec3, time: f32) -> Vec3 {
+        if !self.enabled || direction.y <= 0.01 {
+            return background;
+        }
+
+        let direction = direction.normalize();
+        let t_start = self.bottom / direction.y;
+        let t_end = self.top / direction.y;
+        let step = (t_end - t_start) / CLOUD_STEPS as f32;
+
+        let mut transmittance = 1.0;
+        let mut color = Vec3::zero();
+        for i in 0..CLOUD_STEPS {
+            let point = direction * (t_start + (i as f32 + 0.5) * step);
+            let density = self.density_at(&point, time);
+            if density <= 0.0 {
+                continue;
+            }
+
+            let mut light = ambient;
+            for &(light_dir, light_color) in lights {
+                if light_color == Vec3::zero() {
+                    continue;
+                }
+                let phase = henyey_greenstein(direction.dot(&light_dir), 0.3) * 4.0 * std::f32::consts::PI;
+                light = light + light_color * (self.light_transmittance(&point, &light_dir, time) * phase);
+            }
+
+            let step_transmittance = (-density * step).exp();
+            color = color + light * (transmittance * (1.0 - step_transmittance));
+            transmittance *= step_transmittance;
+            if transmittance < 0.01 {
+                break;
+            }
+        }
+
+        // Distant clouds fade into the haze at the horizon
+        let fade = ((direction.y - 0.01) / 0.15).clamp(0.0, 1.0);
+        background * (1.0 - fade + fade * transmittance) + color * fade
+    }
+
+    // Fraction of light that crosses the cloud layer along `ray` before
+    // `max_t`, marched in a few steps over the part of the ray inside the
+    // layer. Cheap enough for every shadow ray.
+    pub fn shadow(&self, ray: &Ray, max_t: f32, time: f32) -> f32 {
+        if !self.enabled {
+            return 1.0;
+        }
+
+        let (t_start, t_end) = if ray.direction.y.abs() < 1e-4 {
+            if ray.origin.y <= self.bottom || ray.origin.y >= self.top {
+                return 1.0;
+            }
+            (0.0, f32::INFINITY)
+        } else {
+            let t_bottom = (self.bottom - ray.origin.y) / ray.direction.y;
+            let t_top = (self.top - ray.origin.y) / ray.direction.y;
+            (t_bottom.min(t_top).max(0.0), t_bottom.max(t_top))
+        };
+        let t_end = t_end.min(max_t).min(t_start + MAX_SHADOW_MARCH);
+        if t_end <= t_start {
+            return 1.0;
+        }
+
+        let step = (t_end - t_start) / LIGHT_STEPS as f32;
+        let optical_depth: f32 = (0..LIGHT_STEPS)
+            .map(|i| self.density_at(&ray.at(t_start + (i as f32 + 0.5) * step), time) * step)
+            .sum();
+        (-optical_depth).exp()
+    }
+
+    fn light_transmittance(&self, point: &Vec3, light_dir: &Vec3, time: f32) -> f32 {
+        if light_dir.y <= 0.0 {
+            return 0.0;
+        }
+
+        let step = (self.top - point.y) / light_dir.y / LIGHT_STEPS as f32;
+        let mut optical_depth = 0.0;
+        for i in 0..LIGHT_STEPS {
+            let sample = *point + *light_dir * ((i as f32 + 0.5) * step);
+            optical_depth += self.density_at(&sample, time) * step;
+        }
+        (-optical_depth).exp()
+    }
+}
+
+// Trilinearly interpolated hash noise in [0, 1]
+fn value_noise(p: &Vec3) -> f32 {
+    let (x0, y0, z0) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (fx, fy, fz) = (p.x - x0, p.y - y0, p.z - z0);
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (sx, sy, sz) = (smooth(fx), smooth(fy), smooth(fz));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let h = hash((x0 as i32 + dx) as u32 ^ hash((y0 as i32 + dy) as u32 ^ hash((z0 as i32 + dz) as u32)));
+        (h & 0xffff) as f32 / 65535.0
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), sx);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), sx);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), sx);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), sx);
+    lerp(lerp(x00, x10, sy), lerp(x01, x11, sy), sz)
+}
@@ -0,0 +1,87 @@
+use crate::math::Vec3;
+use crate::filter::interleaved_gradient_noise;
+
+// Color-space contract: material albedos are authored in sRGB, as a color
+// picker shows them, and go through `srgb_to_linear` when a material is
+// created or its pattern evaluated. Everything past that point (lighting,
+// emission, sky, fog, post-processing) is linear, and only the framebuffer
+// encodes back to sRGB for the window.
+
+const BAYER_4X4: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+// Threshold noise added before quantizing to 8 bits, which trades the
+// banding of smooth gradients such as the night sky for fine grain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    // 4x4 Bayer matrix
+    Ordered,
+    // Interleaved gradient noise, whose energy sits at high frequencies
+    BlueNoise,
+}
+
+impl Dither {
+    // Offset in [-0.5, 0.5) of a quantization step for pixel `x`, `y`
+    pub fn offset(&self, x: usize, y: usize) -> f32 {
+        match self {
+            Dither::None => 0.0,
+            Dither::Ordered => (BAYER_4X4[y % 4][x % 4] + 0.5) / 16.0 - 0.5,
+            Dither::BlueNoise => interleaved_gradient_noise(x as f32, y as f32) - 0.5,
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            Dither::None => Dither::Ordered,
+            Dither::Ordered => Dither::BlueNoise,
+            Dither::BlueNoise => Dither::None,
+        }
+    }
+}
+
+// Exact sRGB EOTF, from an encoded value in [0, 1] to linear
+pub fn srgb_to_linear_channel(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Exact sRGB OETF, from linear in [0, 1] to an encoded value
+pub fn linear_to_srgb_channel(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    Vec3::new(
+        srgb_to_linear_channel(color.x),
+        srgb_to_linear_channel(color.y),
+        srgb_to_linear_channel(color.z),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip() {
+        for step in 0..=255 {
+            let encoded = step as f32 / 255.0;
+            let decoded = linear_to_srgb_channel(srgb_to_linear_channel(encoded));
+            assert!((decoded - encoded).abs() < 1e-5, "{} -> {}", encoded, decoded);
+        }
+    }
+}
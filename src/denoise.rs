@@ -0,0 +1,129 @@
+use crate::math::Vec3;
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::luminance;
+use rayon::prelude::*;
+
+// B3-spline weights of the 5x5 à-trous kernel, per axis
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+// Albedo below this is treated as this when dividing it out
+const MIN_ALBEDO: f32 = 0.01;
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al.) over the feature
+// buffers the raytracer writes. Albedo is divided out first so textures
+// stay sharp and only the lighting is smoothed; taps across a change in
+// normal, depth or, less and less with every pass, brightness get little
+// weight. The filter gets gentler as frames accumulate and noise drops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    pub enabled: bool,
+    // Passes, each spreading its taps twice as far apart
+    pub iterations: u32,
+    // Luminance difference at which a tap's weight falls to 1/e
+    pub sigma_color: f32,
+    // Exponent on the cosine between normals
+    pub sigma_normal: f32,
+    // Relative depth difference, per pixel of tap distance, at which a
+    // tap's weight falls to 1/e
+    pub sigma_depth: f32,
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Denoiser {
+            enabled: false,
+            iterations: 4,
+            sigma_color: 4.0,
+            sigma_normal: 64.0,
+            sigma_depth: 0.02,
+        }
+    }
+
+    // Filters `framebuffer.display` in place
+    pub fn apply(&self, framebuffer: &mut Framebuffer) {
+        if !self.enabled || self.iterations == 0 {
+            return;
+        }
+
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let mut lighting: Vec<Vec3> = framebuffer
+            .display
+            .par_iter()
+            .zip(framebuffer.albedo.par_iter())
+            .map(|(color, albedo)| demodulate(color, albedo))
+            .collect();
+
+        let mut sigma_color = self.sigma_color / (framebuffer.accumulated_frames.max(1) as f32).sqrt();
+        for iteration in 0..self.iterations {
+            let step = 1isize << iteration;
+            let source = &lighting;
+            lighting = (0..width * height)
+                .into_par_iter()
+                .map(|index| self.filter_pixel(framebuffer, source, index, step, sigma_color))
+                .collect();
+            sigma_color *= 0.5;
+        }
+
+        framebuffer
+            .display
+            .par_iter_mut()
+            .zip(lighting.par_iter())
+            .zip(framebuffer.albedo.par_iter())
+            .for_each(|((color, lighting), albedo)| *color = *lighting * clamp_albedo(albedo));
+    }
+
+    // One à-trous tap pattern around pixel `index` with taps `step` apart
+    fn filter_pixel(&self, framebuffer: &Framebuffer, source: &[Vec3], index: usize, step: isize, sigma_color: f32) -> Vec3 {
+        let (width, height) = (framebuffer.width as isize, framebuffer.height as isize);
+        let (x, y) = (index as isize % width, index as isize / width);
+        let center = source[index];
+        let center_luminance = luminance(&center);
+        let center_normal = framebuffer.normal[index];
+        let center_depth = framebuffer.depth[index];
+
+        let mut sum = Vec3::zero();
+        let mut total = 0.0;
+        for (j, kernel_y) in KERNEL.iter().enumerate() {
+            for (i, kernel_x) in KERNEL.iter().enumerate() {
+                let (qx, qy) = (x + (i as isize - 2) * step, y + (j as isize - 2) * step);
+                if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                    continue;
+                }
+                let q = (qy * width + qx) as usize;
+                let tap_distance = ((i as isize - 2).abs().max((j as isize - 2).abs()) * step) as f32;
+
+                let color_weight = (-(luminance(&source[q]) - center_luminance).abs() / sigma_color.max(1e-4)).exp();
+                let normal_weight = if center_normal == Vec3::zero() && framebuffer.normal[q] == Vec3::zero() {
+                    1.0
+                } else {
+                    center_normal.dot(&framebuffer.normal[q]).max(0.0).powf(self.sigma_normal)
+                };
+                let depth_weight = depth_weight(center_depth, framebuffer.depth[q], self.sigma_depth * tap_distance);
+
+                let weight = kernel_x * kernel_y * color_weight * normal_weight * depth_weight;
+                sum = sum + source[q] * weight;
+                total += weight;
+            }
+        }
+
+        if total > 1e-6 { sum / total } else { center }
+    }
+}
+
+// Weight of a tap at depth `tap` seen from depth `center`. Sky pixels only
+// mix with sky pixels.
+fn depth_weight(center: f32, tap: f32, sigma: f32) -> f32 {
+    match (center.is_finite(), tap.is_finite()) {
+        (false, false) => 1.0,
+        (true, true) => (-(center - tap).abs() / (sigma * center).max(1e-4)).exp(),
+        _ => 0.0,
+    }
+}
+
+fn clamp_albedo(albedo: &Vec3) -> Vec3 {
+    Vec3::new(albedo.x.max(MIN_ALBEDO), albedo.y.max(MIN_ALBEDO), albedo.z.max(MIN_ALBEDO))
+}
+
+fn demodulate(color: &Vec3, albedo: &Vec3) -> Vec3 {
+    let albedo = clamp_albedo(albedo);
+    Vec3::new(color.x / albedo.x, color.y / albedo.y, color.z / albedo.z)
+}
@@ -0,0 +1,144 @@
+use crate::sampling::{Rng, hash};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReconstructionFilter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl ReconstructionFilter {
+    // Half-width in pixels
+    pub fn radius(&self) -> f32 {
+        match self {
+            ReconstructionFilter::Box => 0.5,
+            ReconstructionFilter::Tent => 1.0,
+            ReconstructionFilter::Gaussian => 1.5,
+            ReconstructionFilter::Mitchell => 2.0,
+        }
+    }
+
+    // Separable weight of a sample `dx`, `dy` pixels away from a pixel center
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let d = d.abs();
+        match self {
+            ReconstructionFilter::Box => if d <= 0.5 { 1.0 } else { 0.0 },
+            ReconstructionFilter::Tent => (1.0 - d).max(0.0),
+            ReconstructionFilter::Gaussian => {
+                let alpha = 2.0;
+                let r = self.radius();
+                ((-alpha * d * d).exp() - (-alpha * r * r).exp()).max(0.0)
+            }
+            ReconstructionFilter::Mitchell => {
+                // B = C = 1/3
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                if d < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * d * d * d
+                        + (-18.0 + 12.0 * b + 6.0 * c) * d * d
+                        + (6.0 - 2.0 * b)) / 6.0
+                } else if d < 2.0 {
+                    ((-b - 6.0 * c) * d * d * d
+                        + (6.0 * b + 30.0 * c) * d * d
+                        + (-12.0 * b - 48.0 * c) * d
+                        + (8.0 * b + 24.0 * c)) / 6.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            ReconstructionFilter::Box => ReconstructionFilter::Tent,
+            ReconstructionFilter::Tent => ReconstructionFilter::Gaussian,
+            ReconstructionFilter::Gaussian => ReconstructionFilter::Mitchell,
+            ReconstructionFilter::Mitchell => ReconstructionFilter::Box,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleJitter {
+    // One random point per cell of a near-square grid over the pixel
+    Stratified,
+    // R2 low-discrepancy sequence rotated per pixel by interleaved gradient
+    // noise, which spreads the error as blue noise across the image
+    BlueNoise,
+}
+
+// Sub-pixel sample positions in [0, 1)^2 for pixel `x`, `y`
+pub fn sample_offsets(jitter: SampleJitter, samples: u32, x: usize, y: usize, frame: u32) -> Vec<(f32, f32)> {
+    let samples = samples.max(1);
+
+    match jitter {
+        SampleJitter::Stratified => {
+            let rows = (samples as f32).sqrt().floor().max(1.0) as u32;
+            let cols = samples.div_ceil(rows);
+            let mut rng = Rng::for_pixel(x, y, frame, hash(samples));
+
+            (0..samples)
+                .map(|i| {
+                    let (cx, cy) = (i % cols, i / cols);
+                    (
+                        (cx as f32 + rng.next_f32()) / cols as f32,
+                        (cy as f32 + rng.next_f32()) / rows as f32,
+                    )
+                })
+                .collect()
+        }
+        SampleJitter::BlueNoise => {
+            const A1: f32 = 0.754_877_7;
+            const A2: f32 = 0.569_840_3;
+            let frame_offset = (frame % 64) as f32 * 5.588_238;
+            let px = x as f32 + frame_offset;
+            let py = y as f32 + frame_offset;
+            let seed_x = interleaved_gradient_noise(px, py);
+            let seed_y = interleaved_gradient_noise(px + 47.0, py + 17.0);
+            let start = ((frame % 1024) * samples) as f32;
+
+            (0..samples)
+                .map(|i| {
+                    let n = start + i as f32;
+                    ((seed_x + n * A1).fract(), (seed_y + n * A2).fract())
+                })
+                .collect()
+        }
+    }
+}
+
+// Jorge Jimenez' interleaved gradient noise
+pub fn interleaved_gradient_noise(x: f32, y: f32) -> f32 {
+    (52.982_918 * (0.067_110_56 * x + 0.005_837_15 * y).fract()).fract()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [ReconstructionFilter; 4] = [
+        ReconstructionFilter::Box,
+        ReconstructionFilter::Tent,
+        ReconstructionFilter::Gaussian,
+        ReconstructionFilter::Mitchell,
+    ];
+
+    #[test]
+    fn weights_vanish_outside_radius() {
+        for filter in FILTERS {
+            let radius = filter.radius();
+            assert!(filter.weight(0.0, 0.0) > 0.0, "{:?}", filter);
+            for step in 1..=20 {
+                let d = radius + step as f32 * 0.05;
+                assert_eq!(filter.weight(d, 0.0), 0.0, "{:?} at {}", filter, d);
+                assert_eq!(filter.weight(0.0, -d), 0.0, "{:?} at {}", filter, -d);
+                assert_eq!(filter.weight(d, d), 0.0, "{:?} at {}", filter, d);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::math::{Vec3, Ray};
+use crate::scene::Scene;
+use crate::sampling::Rng;
+use std::f32::consts::PI;
+
+// Exponential height fog: density halves every `ln 2 / height_falloff`
+// units above `base_height`. Camera rays march through it and gather single
+// scattering from every light, shadowed by the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub enabled: bool,
+    // Extinction per unit length at `base_height`
+    pub density: f32,
+    pub height_falloff: f32,
+    pub base_height: f32,
+    // Scattering albedo, tints the haze
+    pub color: Vec3,
+    // Henyey-Greenstein g: 0 scatters evenly, towards 1 mostly forward
+    pub anisotropy: f32,
+    pub steps: u32,
+    // Rays that escape to the sky only see fog up to this distance
+    pub max_distance: f32,
+}
+
+impl Fog {
+    pub fn new() -> Self {
+        Fog {
+            enabled: false,
+            density: 0.06,
+            height_falloff: 0.35,
+            base_height: -0.6,
+            color: Vec3::new(0.85, 0.88, 0.95),
+            anisotropy: 0.6,
+            steps: 12,
+            max_distance: 40.0,
+        }
+    }
+
+    pub fn density_at(&self, height: f32) -> f32 {
+        self.density * (-self.height_falloff * (height - self.base_height)).exp()
+    }
+
+    // Fraction of light that crosses the first `distance` units of `ray`
+    pub fn transmittance(&self, ray: &Ray, distance: f32) -> f32 {
+        if !self.enabled {
+            return 1.0;
+        }
+
+        // Closed-form integral of the density along the ray
+        let start = self.density_at(ray.origin.y);
+        let k = self.height_falloff * ray.direction.y;
+        let optical_depth = if k.abs() < 1e-4 {
+            start * distance
+        } else {
+            start * (1.0 - (-k * distance).exp()) / k
+        };
+        (-optical_depth).exp()
+    }
+
+    // Transmittance over the first `distance` units of `ray` and the light
+    // scattered towards its origin along them. Ambient light always
+    // scatters; passing `rng` also ray-marches the scene's lights, which
+    // gives light shafts and halos but costs a shadow ray per step and light.
+    pub fn segment(&self, ray: &Ray, distance: f32, scene: &Scene, rng: Option<&mut Rng>) -> (f32, Vec3) {
+        if !self.enabled {
+            return (1.0, Vec3::zero());
+        }
+
+        let distance = distance.min(self.max_distance);
+        let transmittance = self.transmittance(ray, distance);
+        let mut in_scattered = scene.ambient_light * (1.0 - transmittance);
+
+        if let Some(rng) = rng {
+            let step = distance / self.steps.max(1) as f32;
+            let jitter = rng.next_f32();
+
+            for i in 0..self.steps {
+                let t = (i as f32 + jitter) * step;
+                let point = ray.at(t);
+                let weight = self.density_at(point.y) * step * self.transmittance(ray, t);
+
+                for light in scene.lights_near(&point, ray.time) {
+                    let emission = light.emission();
+                    if emission == Vec3::zero() || light.direction_from(&point).1 > light.range {
+                        continue;
+                    }
+
+                    let (light_dir, light_distance) = light.sample_direction(&point, rng);
+                    let shadow_ray = Ray::with_time(point, light_dir, ray.time);
+                    let visibility = scene.light_transmittance(&shadow_ray, &light, light_distance)
+                        * self.transmittance(&shadow_ray, light_distance.min(self.max_distance));
+
+                    let phase = henyey_greenstein(ray.direction.dot(&light_dir), self.anisotropy);
+                    in_scattered = in_scattered
+                        + emission * visibility * (weight * phase * light.attenuation(light_distance, scene.sun_intensity));
+                }
+            }
+        }
+
+        (transmittance, in_scattered * self.color)
+    }
+
+    // `color` seen through the first `distance` units of `ray`
+    pub fn apply(&self, ray: &Ray, distance: f32, color: Vec3, scene: &Scene, rng: Option<&mut Rng>) -> Vec3 {
+        let (transmittance, in_scattered) = self.segment(ray, distance, scene, rng);
+        color * transmittance + in_scattered
+    }
+}
+
+// Phase function for light arriving along `cos_theta` relative to the
+// scattered direction, normalized over the sphere
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+}
@@ -1,9 +1,83 @@
 use crate::math::Vec3;
+use crate::filter::ReconstructionFilter;
+use crate::scene::HitInfo;
+use crate::tonemap::{ToneMapOperator, luminance};
+use crate::color::{Dither, linear_to_srgb_channel};
+
+// Auto-exposure meters log2 luminance over this range of stops
+const HISTOGRAM_MIN_EV: f32 = -12.0;
+const HISTOGRAM_MAX_EV: f32 = 6.0;
+const HISTOGRAM_BINS: usize = 64;
+// Share of pixels below and above which the meter ignores the image, so
+// the sun disk or a black corner do not swing the exposure
+const METER_LOW_PERCENTILE: f32 = 0.4;
+const METER_HIGH_PERCENTILE: f32 = 0.95;
+// Average luminance auto-exposure maps to, and how far it may push
+const MIDDLE_GREY: f32 = 0.18;
+const AUTO_EXPOSURE_RANGE: (f32, f32) = (-3.0, 5.0);
+// Rate of eye adaptation per second
+const ADAPTATION_SPEED: f32 = 1.5;
+
+// What a camera ray first hits, as seen by the passes that reproject or
+// filter the image
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    pub depth: f32,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+}
+
+impl Features {
+    pub fn from_hit(hit: &HitInfo) -> Self {
+        if hit.hit {
+            Features { depth: hit.distance, normal: hit.normal, albedo: hit.material.albedo }
+        } else {
+            Features { depth: f32::INFINITY, normal: Vec3::zero(), albedo: Vec3::one() }
+        }
+    }
+
+    // Mean normal and albedo of the samples in one pixel and the nearest depth
+    pub fn average(samples: &[Features]) -> Self {
+        let count = samples.len().max(1) as f32;
+        let (mut depth, mut normal, mut albedo) = (f32::INFINITY, Vec3::zero(), Vec3::zero());
+        for sample in samples {
+            depth = depth.min(sample.depth);
+            normal = normal + sample.normal;
+            albedo = albedo + sample.albedo;
+        }
+        Features { depth, normal: normal / count, albedo: albedo / count }
+    }
+}
 
 pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
     pub buffer: Vec<Vec3>,
+    // Filter-weighted sum of every sample since the last reset and the sum of
+    // the weights; `buffer` holds their ratio
+    accumulation: Vec<Vec3>,
+    weights: Vec<f32>,
+    // Plain sum and count of the samples inside each pixel, used where the
+    // negative lobes of a filter leave the weight sum non-positive
+    box_accumulation: Vec<Vec3>,
+    box_weights: Vec<f32>,
+    pub accumulated_frames: u32,
+    // What gets shown: a copy of `buffer` that post-processing stages such
+    // as bloom may rewrite without touching the accumulation
+    pub display: Vec<Vec3>,
+    // Features of what the camera rays through each pixel hit, averaged
+    // over every frame since the last reset: distance from the camera,
+    // surface normal and albedo. The sky has infinite depth, a zero normal
+    // and white albedo.
+    pub depth: Vec<f32>,
+    pub normal: Vec<Vec3>,
+    pub albedo: Vec<Vec3>,
+    pub tone_map: ToneMapOperator,
+    // Manual exposure in stops, on top of auto-exposure when it is enabled
+    pub exposure: f32,
+    pub auto_exposure: bool,
+    adapted_exposure: f32,
+    pub dither: Dither,
 }
 
 impl Framebuffer {
@@ -12,32 +86,153 @@ impl Framebuffer {
             width,
             height,
             buffer: vec![Vec3::zero(); width * height],
+            accumulation: vec![Vec3::zero(); width * height],
+            weights: vec![0.0; width * height],
+            box_accumulation: vec![Vec3::zero(); width * height],
+            box_weights: vec![0.0; width * height],
+            accumulated_frames: 0,
+            display: vec![Vec3::zero(); width * height],
+            depth: vec![f32::INFINITY; width * height],
+            normal: vec![Vec3::zero(); width * height],
+            albedo: vec![Vec3::one(); width * height],
+            tone_map: ToneMapOperator::AcesFilmic,
+            exposure: 0.0,
+            auto_exposure: false,
+            adapted_exposure: 0.0,
+            dither: Dither::BlueNoise,
         }
     }
 
     pub fn clear(&mut self) {
         self.buffer.fill(Vec3::zero());
+        self.display.fill(Vec3::zero());
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.clear();
+        self.accumulation.fill(Vec3::zero());
+        self.weights.fill(0.0);
+        self.box_accumulation.fill(Vec3::zero());
+        self.box_weights.fill(0.0);
+        self.accumulated_frames = 0;
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, color: Vec3) {
-        if x < self.width && y < self.height {
-            let index = y * self.width + x;
-            self.buffer[index] = color;
+    // Splats one frame worth of samples into every pixel within the filter
+    // radius and refreshes the displayed image. Sample positions are in
+    // pixel units with (0, 0) at the top-left corner of the image.
+    pub fn accumulate_samples(&mut self, samples: &[(f32, f32, Vec3)], filter: &ReconstructionFilter) {
+        let radius = filter.radius();
+
+        for &(sx, sy, color) in samples {
+            let x0 = (sx - radius - 0.5).ceil().max(0.0) as usize;
+            let y0 = (sy - radius - 0.5).ceil().max(0.0) as usize;
+            let x1 = ((sx + radius - 0.5).floor().max(0.0) as usize).min(self.width - 1);
+            let y1 = ((sy + radius - 0.5).floor().max(0.0) as usize).min(self.height - 1);
+
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let weight = filter.weight(sx - (x as f32 + 0.5), sy - (y as f32 + 0.5));
+                    if weight != 0.0 {
+                        let index = y * self.width + x;
+                        self.accumulation[index] = self.accumulation[index] + color * weight;
+                        self.weights[index] += weight;
+                    }
+                }
+            }
+
+            let (x, y) = (sx.max(0.0) as usize, sy.max(0.0) as usize);
+            if x < self.width && y < self.height {
+                let index = y * self.width + x;
+                self.box_accumulation[index] = self.box_accumulation[index] + color;
+                self.box_weights[index] += 1.0;
+            }
         }
+
+        self.accumulated_frames += 1;
+        for (index, pixel) in self.buffer.iter_mut().enumerate() {
+            if self.weights[index] > 1e-6 {
+                *pixel = self.accumulation[index] / self.weights[index];
+            } else if self.box_weights[index] > 0.0 {
+                *pixel = self.box_accumulation[index] / self.box_weights[index];
+            }
+        }
+        self.display.copy_from_slice(&self.buffer);
+    }
+
+    // Folds one frame of per-pixel features into the running averages.
+    // Call after `accumulate_samples` for the same frame.
+    pub fn accumulate_features(&mut self, features: &[Features]) {
+        let blend = 1.0 / self.accumulated_frames.max(1) as f32;
+        for (index, pixel) in features.iter().enumerate() {
+            self.depth[index] = if self.accumulated_frames <= 1 { pixel.depth } else { self.depth[index].min(pixel.depth) };
+            self.normal[index] = self.normal[index].lerp(&pixel.normal, blend);
+            self.albedo[index] = self.albedo[index].lerp(&pixel.albedo, blend);
+        }
+    }
+
+    // Moves the auto-exposure towards the level that brings the metered
+    // part of `display` to middle grey, as an eye adapting over `delta_time`
+    pub fn update_exposure(&mut self, delta_time: f32) {
+        if !self.auto_exposure {
+            return;
+        }
+
+        let mut histogram = [0u32; HISTOGRAM_BINS];
+        let bin_width = (HISTOGRAM_MAX_EV - HISTOGRAM_MIN_EV) / HISTOGRAM_BINS as f32;
+        for color in &self.display {
+            let ev = luminance(color).max(1e-6).log2();
+            let bin = ((ev - HISTOGRAM_MIN_EV) / bin_width).clamp(0.0, (HISTOGRAM_BINS - 1) as f32);
+            histogram[bin as usize] += 1;
+        }
+
+        // Mean log luminance of the pixels between the two percentiles
+        let total = self.display.len() as f32;
+        let (low, high) = (total * METER_LOW_PERCENTILE, total * METER_HIGH_PERCENTILE);
+        let (mut seen, mut weight, mut sum) = (0.0, 0.0, 0.0);
+        for (bin, count) in histogram.iter().enumerate() {
+            let count = *count as f32;
+            let counted = (seen + count).min(high) - seen.max(low);
+            if counted > 0.0 {
+                sum += counted * (HISTOGRAM_MIN_EV + (bin as f32 + 0.5) * bin_width);
+                weight += counted;
+            }
+            seen += count;
+        }
+        if weight <= 0.0 {
+            return;
+        }
+
+        let target = (MIDDLE_GREY.log2() - sum / weight).clamp(AUTO_EXPOSURE_RANGE.0, AUTO_EXPOSURE_RANGE.1);
+        let blend = 1.0 - (-delta_time * ADAPTATION_SPEED).exp();
+        self.adapted_exposure += (target - self.adapted_exposure) * blend;
+    }
+
+    // Exposure in stops the displayed image is scaled by
+    pub fn total_exposure(&self) -> f32 {
+        if self.auto_exposure { self.exposure + self.adapted_exposure } else { self.exposure }
     }
 
-    fn vec3_to_u32(&self, color: Vec3) -> u32 {
-        let r = (color.x.clamp(0.0, 1.0).sqrt() * 255.0) as u32;
-        let g = (color.y.clamp(0.0, 1.0).sqrt() * 255.0) as u32;
-        let b = (color.z.clamp(0.0, 1.0).sqrt() * 255.0) as u32;
+    // Tone maps linear `color` and encodes it with the sRGB OETF, dithered
+    // by `dither` quantization steps
+    fn vec3_to_u32(&self, color: Vec3, scale: f32, dither: f32) -> u32 {
+        let color = self.tone_map.apply(color * scale);
+        let encode = |value: f32| (linear_to_srgb_channel(value) * 255.0 + dither).round().clamp(0.0, 255.0) as u32;
+        let r = encode(color.x);
+        let g = encode(color.y);
+        let b = encode(color.z);
         
         (r << 16) | (g << 8) | b
     }
 
     pub fn to_u32_buffer(&self) -> Vec<u32> {
-        self.buffer
+        let scale = self.total_exposure().exp2();
+        self.display
             .iter()
-            .map(|color| self.vec3_to_u32(*color))
+            .enumerate()
+            .map(|(index, color)| {
+                let dither = self.dither.offset(index % self.width, index / self.width);
+                self.vec3_to_u32(*color, scale, dither)
+            })
             .collect()
     }
 }
@@ -0,0 +1,210 @@
+use crate::math::{Vec3, Ray};
+use crate::scene::{Scene, Cube};
+use crate::path_tracer::sample_light;
+use crate::sampling::{Rng, cosine_hemisphere};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+const CELL_SIZE: f32 = 0.5;
+// Samples an entry keeps averaging while the scene time stands still;
+// once the sun moves, history is cut back to this many so the cache follows
+const MAX_MOVING_HISTORY: u32 = 8;
+
+const AXES: [Vec3; 6] = [
+    Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+    Vec3 { x: -1.0, y: 0.0, z: 0.0 },
+    Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+    Vec3 { x: 0.0, y: -1.0, z: 0.0 },
+    Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+    Vec3 { x: 0.0, y: 0.0, z: -1.0 },
+];
+
+// Grid cell in front of a face, and which of the six axis directions the
+// face looks towards
+type FaceKey = (i32, i32, i32, u8);
+
+#[derive(Debug, Clone, Copy)]
+struct CacheEntry {
+    point: Vec3,
+    normal: Vec3,
+    // Cosine-weighted average of the radiance arriving at `point`, so a
+    // diffuse surface reflects `albedo * irradiance`
+    irradiance: Vec3,
+    samples: u32,
+}
+
+// One-bounce diffuse lighting stored per block face patch. Every face of
+// every cube is split into `CELL_SIZE` patches; each frame a budget of
+// patches gathers light with short hemisphere rays, round-robin, and blends
+// it into what they had. Shading then reads the patch under the hit point
+// instead of a flat ambient term.
+#[derive(Debug, Clone)]
+pub struct IrradianceCache {
+    pub enabled: bool,
+    pub updates_per_frame: usize,
+    pub rays_per_update: u32,
+    entries: Vec<CacheEntry>,
+    index: HashMap<FaceKey, usize>,
+    cursor: usize,
+    time: f32,
+}
+
+impl IrradianceCache {
+    pub fn new() -> Self {
+        IrradianceCache {
+            enabled: false,
+            updates_per_frame: 512,
+            rays_per_update: 16,
+            entries: Vec::new(),
+            index: HashMap::new(),
+            cursor: 0,
+            time: f32::NAN,
+        }
+    }
+
+    // Lays out the face patches of the scene's cubes; spheres are left to
+    // the ambient term
+    fn build(&mut self, cubes: &[Cube]) {
+        self.entries.clear();
+        self.index.clear();
+        self.cursor = 0;
+
+        for cube in cubes {
+            let min = cube.center - cube.size * 0.5;
+            let max = cube.center + cube.size * 0.5;
+            for (face, normal) in AXES.iter().enumerate() {
+                let axis = face / 2;
+                let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+                let plane = if face % 2 == 0 { component(&max, axis) } else { component(&min, axis) };
+
+                for u in patch_centers(component(&min, u_axis), component(&max, u_axis)) {
+                    for v in patch_centers(component(&min, v_axis), component(&max, v_axis)) {
+                        let mut point = [0.0; 3];
+                        point[axis] = plane;
+                        point[u_axis] = u;
+                        point[v_axis] = v;
+                        let point = Vec3::new(point[0], point[1], point[2]);
+
+                        let key = face_key(&point, face);
+                        if !self.index.contains_key(&key) {
+                            self.index.insert(key, self.entries.len());
+                            self.entries.push(CacheEntry { point, normal: *normal, irradiance: Vec3::zero(), samples: 0 });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Refreshes the next `updates_per_frame` patches. The first call lays
+    // out the patches; after that the scene's cubes are assumed not to move.
+    pub fn update(&mut self, scene: &Scene, frame: u32) {
+        if !self.enabled {
+            return;
+        }
+        if self.entries.is_empty() {
+            self.build(&scene.cubes);
+        }
+        if self.entries.is_empty() {
+            return;
+        }
+
+        if scene.time != self.time {
+            for entry in &mut self.entries {
+                entry.samples = entry.samples.min(MAX_MOVING_HISTORY);
+            }
+            self.time = scene.time;
+        }
+
+        let count = self.updates_per_frame.min(self.entries.len());
+        let start = self.cursor;
+        let total = self.entries.len();
+        let rays = self.rays_per_update.max(1);
+
+        let gathered: Vec<(usize, Vec3)> = (0..count)
+            .into_par_iter()
+            .map(|offset| {
+                let index = (start + offset) % total;
+                let mut rng = Rng::for_pixel(index, 0, frame, 0);
+                (index, gather(&self.entries[index], scene, rays, &mut rng))
+            })
+            .collect();
+
+        for (index, irradiance) in gathered {
+            let entry = &mut self.entries[index];
+            entry.samples += 1;
+            entry.irradiance = entry.irradiance.lerp(&irradiance, 1.0 / entry.samples as f32);
+        }
+        self.cursor = (start + count) % total;
+    }
+
+    // Cached irradiance on the face patch under `point`, if the hit is on a
+    // cube face with a patch that has been gathered at least once
+    pub fn lookup(&self, point: &Vec3, normal: &Vec3) -> Option<Vec3> {
+        if !self.enabled {
+            return None;
+        }
+
+        let entry = &self.entries[*self.index.get(&face_key(point, cube_face(normal)?))?];
+        (entry.samples > 0).then_some(entry.irradiance)
+    }
+}
+
+// Average radiance over cosine-weighted directions above the patch: the sky
+// where rays escape, and direct light reflected by whatever they hit.
+// Emission and the sun disk are left out because `trace_ray` already
+// samples every light.
+fn gather(entry: &CacheEntry, scene: &Scene, rays: u32, rng: &mut Rng) -> Vec3 {
+    let origin = entry.point + entry.normal * 0.001;
+    let mut sum = Vec3::zero();
+
+    for _ in 0..rays {
+        let direction = cosine_hemisphere(&entry.normal, rng.next_f32(), rng.next_f32());
+        let ray = Ray::new(origin, direction);
+        let hit = scene.intersect(&ray);
+        if !hit.hit {
+            sum = sum + scene.get_sky_color(&direction, false);
+            continue;
+        }
+
+        let normal = if direction.dot(&hit.normal) < 0.0 { hit.normal } else { hit.normal * -1.0 };
+        sum = sum + hit.material.albedo * sample_light(scene, &hit.point, &normal, 0.0, rng);
+    }
+
+    sum / rays as f32
+}
+
+// Patch along the face plane, and along the normal the cell half a patch in
+// front of the plane, so hits a little off the plane still find their face
+fn face_key(point: &Vec3, face: usize) -> FaceKey {
+    let cell = (*point + AXES[face] * (CELL_SIZE * 0.5)) / CELL_SIZE;
+    (cell.x.floor() as i32, cell.y.floor() as i32, cell.z.floor() as i32, face as u8)
+}
+
+// Index into `AXES` of a cube face normal. Cubes report their face normals
+// exactly; any other normal, such as a sphere's, has no patch.
+fn cube_face(normal: &Vec3) -> Option<usize> {
+    AXES.iter().position(|axis| axis == normal)
+}
+
+fn component(v: &Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+// Centers of the grid cells that [min, max] overlaps, clipped to it, so
+// every patch a hit on the face can look up gets an entry
+fn patch_centers(min: f32, max: f32) -> Vec<f32> {
+    let first = (min / CELL_SIZE).floor() as i32;
+    let last = ((max / CELL_SIZE).ceil() as i32 - 1).max(first);
+    (first..=last)
+        .map(|cell| {
+            let low = (cell as f32 * CELL_SIZE).max(min);
+            let high = ((cell + 1) as f32 * CELL_SIZE).min(max);
+            (low + high) * 0.5
+        })
+        .collect()
+}
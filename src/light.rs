@@ -0,0 +1,270 @@
+use crate::math::{Vec3, Ray};
+use crate::bvh::{Aabb, Primitive};
+use crate::scene::{Cube, Sphere};
+use crate::sampling::{Rng, concentric_disk, orthonormal_basis};
+
+// Contribution below which a light is considered out of reach
+const LIGHT_CUTOFF: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy)]
+pub enum LightShape {
+    Point { position: Vec3 },
+    Sphere { center: Vec3, radius: f32 },
+    // `direction` points from the scene towards the light
+    Directional { direction: Vec3 },
+    Box { center: Vec3, size: Vec3 },
+    // Rectangle spanned by the half-extents `u` and `v`. It lights one side
+    // only: it lies on a face of the panel it belongs to, whose body
+    // shadows the other side.
+    Rect { center: Vec3, u: Vec3, v: Vec3 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub shape: LightShape,
+    pub color: Vec3,
+    // Scales `color`, so dimmer emitters can share a material
+    pub intensity: f32,
+    pub linear_attenuation: f32,
+    pub quadratic_attenuation: f32,
+    // Extra strength at night, scaled by how dark the sun is
+    pub night_boost: f32,
+    // Influence radius, derived from intensity and attenuation by `update_range`
+    pub range: f32,
+    // Geometry this light follows when the scene moves it
+    pub source: Option<Primitive>,
+    // Sky bodies shine from above the cloud layer, however low the scene
+    // places them, so their shadow rays always cross it
+    pub above_clouds: bool,
+}
+
+impl Light {
+    pub fn point(position: Vec3, color: Vec3) -> Self {
+        Light {
+            shape: LightShape::Point { position },
+            color,
+            intensity: 1.0,
+            linear_attenuation: 0.0,
+            quadratic_attenuation: 0.0,
+            night_boost: 0.0,
+            range: f32::INFINITY,
+            source: None,
+            above_clouds: false,
+        }
+    }
+
+    // Area light without falloff, for distant bodies like the sun
+    pub fn sphere(center: Vec3, radius: f32, color: Vec3) -> Self {
+        Light {
+            shape: LightShape::Sphere { center, radius },
+            above_clouds: true,
+            ..Light::point(center, color)
+        }
+    }
+
+    // Light from a body so far away that its rays arrive parallel
+    pub fn directional(direction: Vec3, color: Vec3) -> Self {
+        Light {
+            shape: LightShape::Directional { direction: direction.normalize() },
+            above_clouds: true,
+            ..Light::point(Vec3::zero(), color)
+        }
+    }
+
+    pub fn emissive_cube(index: usize, cube: &Cube) -> Self {
+        Light {
+            shape: LightShape::Box { center: cube.center, size: cube.size },
+            color: cube.material.emissive,
+            intensity: 1.0,
+            linear_attenuation: 0.05,
+            quadratic_attenuation: 0.005,
+            night_boost: 0.8,
+            range: f32::INFINITY,
+            source: Some(Primitive::Cube(index)),
+            above_clouds: false,
+        }
+    }
+
+    // Thin emissive slab lighting from its face on the positive side of its
+    // thinnest axis
+    pub fn emissive_panel(index: usize, cube: &Cube) -> Self {
+        Light {
+            shape: Light::panel_shape(cube),
+            ..Light::emissive_cube(index, cube)
+        }
+    }
+
+    pub fn panel_shape(cube: &Cube) -> LightShape {
+        let half = cube.size * 0.5;
+        let (offset, u, v) = if half.y <= half.x && half.y <= half.z {
+            (Vec3::new(0.0, half.y, 0.0), Vec3::new(half.x, 0.0, 0.0), Vec3::new(0.0, 0.0, half.z))
+        } else if half.x <= half.z {
+            (Vec3::new(half.x, 0.0, 0.0), Vec3::new(0.0, half.y, 0.0), Vec3::new(0.0, 0.0, half.z))
+        } else {
+            (Vec3::new(0.0, 0.0, half.z), Vec3::new(half.x, 0.0, 0.0), Vec3::new(0.0, half.y, 0.0))
+        };
+        LightShape::Rect { center: cube.center + offset, u, v }
+    }
+
+    pub fn emissive_sphere(index: usize, sphere: &Sphere) -> Self {
+        Light {
+            shape: LightShape::Sphere { center: sphere.center, radius: sphere.radius },
+            color: sphere.material.emissive,
+            intensity: 1.0,
+            linear_attenuation: 0.08,
+            quadratic_attenuation: 0.008,
+            night_boost: 1.2,
+            range: f32::INFINITY,
+            source: Some(Primitive::Sphere(index)),
+            above_clouds: false,
+        }
+    }
+
+    // Point used for shading; area lights are treated as their center
+    pub fn position(&self) -> Option<Vec3> {
+        match self.shape {
+            LightShape::Point { position } => Some(position),
+            LightShape::Sphere { center, .. } => Some(center),
+            LightShape::Box { center, .. } => Some(center),
+            LightShape::Rect { center, .. } => Some(center),
+            LightShape::Directional { .. } => None,
+        }
+    }
+
+    // Direction towards the light and the distance to its center
+    pub fn direction_from(&self, point: &Vec3) -> (Vec3, f32) {
+        match self.position() {
+            Some(position) => {
+                let to_light = position - *point;
+                (to_light.normalize(), to_light.length())
+            }
+            None => match self.shape {
+                LightShape::Directional { direction } => (direction.normalize(), f32::INFINITY),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    // Whether shading needs more than one sample for soft shadows
+    pub fn is_area(&self) -> bool {
+        matches!(self.shape, LightShape::Sphere { .. } | LightShape::Box { .. } | LightShape::Rect { .. })
+    }
+
+    // Direction and distance from `point` towards a random point on the
+    // light. Spheres are sampled over the disk they cover as seen from
+    // `point`, boxes over their volume and rectangles over their area, so
+    // the light's size sets the width of the penumbra.
+    pub fn sample_direction(&self, point: &Vec3, rng: &mut Rng) -> (Vec3, f32) {
+        let target = match self.shape {
+            LightShape::Point { .. } | LightShape::Directional { .. } => return self.direction_from(point),
+            LightShape::Sphere { center, radius } => {
+                let (tangent, bitangent) = orthonormal_basis(&(center - *point).normalize());
+                let (disk_x, disk_y) = concentric_disk(rng.next_f32(), rng.next_f32());
+                center + tangent * (disk_x * radius) + bitangent * (disk_y * radius)
+            }
+            LightShape::Box { center, size } => {
+                let offset = Vec3::new(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5);
+                center + size * offset
+            }
+            LightShape::Rect { center, u, v } => {
+                center + u * (2.0 * rng.next_f32() - 1.0) + v * (2.0 * rng.next_f32() - 1.0)
+            }
+        };
+
+        let to_light = target - *point;
+        (to_light.normalize(), to_light.length())
+    }
+
+    // How far a shadow ray leaving `origin` along `direction` may travel
+    // before it reaches the light's own surface, so lights with a body do
+    // not shadow themselves
+    pub fn shadow_distance(&self, origin: &Vec3, direction: &Vec3, distance: f32) -> f32 {
+        match self.shape {
+            LightShape::Point { .. } | LightShape::Directional { .. } => distance,
+            LightShape::Sphere { radius, .. } => (distance - radius - 0.001).max(0.0),
+            // Samples lie on the panel's own face
+            LightShape::Rect { .. } => (distance - 0.001).max(0.0),
+            LightShape::Box { center, size } => {
+                let bounds = Aabb { min: center - size * 0.5, max: center + size * 0.5 };
+                let ray = Ray::new(*origin, *direction);
+                let inv_dir = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+                match bounds.hit(&ray, &inv_dir, distance) {
+                    Some(t) => (t - 0.001).max(0.0),
+                    None => distance,
+                }
+            }
+        }
+    }
+
+    pub fn emission(&self) -> Vec3 {
+        self.color * self.intensity
+    }
+
+    // Distance at which the strongest channel drops below `LIGHT_CUTOFF`.
+    // Lights without falloff reach everywhere.
+    pub fn update_range(&mut self, sun_intensity: f32) {
+        if self.linear_attenuation <= 0.0 && self.quadratic_attenuation <= 0.0 {
+            self.range = f32::INFINITY;
+            return;
+        }
+
+        let emission = self.emission();
+        let peak = emission.x.max(emission.y).max(emission.z) * (1.0 + (1.0 - sun_intensity) * self.night_boost);
+        let ratio = peak / LIGHT_CUTOFF;
+        if ratio <= 1.0 {
+            self.range = 0.0;
+            return;
+        }
+
+        // Solve 1 + l * d + q * d^2 = ratio for d
+        let (l, q) = (self.linear_attenuation, self.quadratic_attenuation);
+        self.range = if q > 0.0 {
+            (-l + (l * l + 4.0 * q * (ratio - 1.0)).sqrt()) / (2.0 * q)
+        } else {
+            (ratio - 1.0) / l
+        };
+    }
+
+    pub fn attenuation(&self, distance: f32, sun_intensity: f32) -> f32 {
+        let base = if distance.is_finite() {
+            1.0 / (1.0 + self.linear_attenuation * distance + self.quadratic_attenuation * distance * distance)
+        } else {
+            1.0
+        };
+
+        // Fade smoothly to zero at the range so culled lights do not pop
+        let window = if self.range.is_finite() {
+            let x = distance / self.range;
+            (1.0 - x * x * x * x).clamp(0.0, 1.0).powi(2)
+        } else {
+            1.0
+        };
+
+        base * window * (1.0 + (1.0 - sun_intensity) * self.night_boost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falloff_reaches_cutoff_at_range() {
+        for (linear, quadratic) in [(0.05, 0.005), (0.08, 0.0), (0.0, 0.01)] {
+            let mut light = Light {
+                linear_attenuation: linear,
+                quadratic_attenuation: quadratic,
+                night_boost: 0.8,
+                ..Light::point(Vec3::zero(), Vec3::new(3.0, 1.5, 0.3))
+            };
+            for sun_intensity in [0.2, 1.0] {
+                light.update_range(sun_intensity);
+                let d = light.range;
+                let falloff = 1.0 / (1.0 + linear * d + quadratic * d * d);
+                let peak = 3.0 * (1.0 + (1.0 - sun_intensity) * light.night_boost);
+                assert!((peak * falloff - LIGHT_CUTOFF).abs() < 1e-4, "{} at {}", peak * falloff, d);
+                assert_eq!(light.attenuation(d, sun_intensity), 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::math::Vec3;
+use crate::bvh::Aabb;
+use crate::light::Light;
+use crate::voxel_grid::cell_coords;
+
+const MAX_CELLS_PER_AXIS: f32 = 32.0;
+const MIN_CELL_SIZE: f32 = 1.0;
+// Lights are inserted with their range grown by this factor plus
+// `RANGE_MARGIN`, so flickering and small motions stay inside the grid
+const RANGE_SLACK: f32 = 1.25;
+const RANGE_MARGIN: f32 = 0.5;
+
+// Uniform grid over the lights' spheres of influence. Each cell lists the
+// lights whose range overlaps it; lights without a finite range (the sun,
+// directional lights) are returned for every point. Ranges are padded so
+// the grid only needs rebuilding once a light outgrows its cells.
+#[derive(Debug, Clone)]
+pub struct LightGrid {
+    origin: Vec3,
+    cell_size: f32,
+    dims: [usize; 3],
+    cell_start: Vec<usize>,
+    cell_lights: Vec<usize>,
+    unbounded: Vec<usize>,
+    // Position and padded range each light was inserted with, None for
+    // unbounded lights
+    inserted: Vec<Option<(Vec3, f32)>>,
+}
+
+impl LightGrid {
+    pub fn build(lights: &[Light]) -> Self {
+        let mut unbounded = Vec::new();
+        let mut bounded = Vec::new();
+        let mut bounds = Aabb::empty();
+        let mut inserted = Vec::with_capacity(lights.len());
+
+        for (index, light) in lights.iter().enumerate() {
+            match light.position() {
+                Some(position) if light.range.is_finite() => {
+                    if light.range > 0.0 {
+                        let range = light.range * RANGE_SLACK + RANGE_MARGIN;
+                        bounds = bounds.union(&influence_bounds(&position, range));
+                        bounded.push((index, position, range));
+                        inserted.push(Some((position, range)));
+                    } else {
+                        inserted.push(Some((position, 0.0)));
+                    }
+                }
+                _ => {
+                    unbounded.push(index);
+                    inserted.push(None);
+                }
+            }
+        }
+
+        if bounded.is_empty() {
+            return LightGrid {
+                origin: Vec3::zero(),
+                cell_size: MIN_CELL_SIZE,
+                dims: [0, 0, 0],
+                cell_start: vec![0],
+                cell_lights: Vec::new(),
+                unbounded,
+                inserted,
+            };
+        }
+
+        let extent = bounds.max - bounds.min;
+        let cell_size = (extent.x.max(extent.y).max(extent.z) / MAX_CELLS_PER_AXIS).max(MIN_CELL_SIZE);
+        let dims = [
+            ((extent.x / cell_size).ceil() as usize).max(1),
+            ((extent.y / cell_size).ceil() as usize).max(1),
+            ((extent.z / cell_size).ceil() as usize).max(1),
+        ];
+
+        let mut cells: Vec<Vec<usize>> = vec![Vec::new(); dims[0] * dims[1] * dims[2]];
+        for &(index, position, range) in &bounded {
+            let light_bounds = influence_bounds(&position, range);
+            let lo = cell_coords(&(light_bounds.min - bounds.min), cell_size, &dims);
+            let hi = cell_coords(&(light_bounds.max - bounds.min), cell_size, &dims);
+            for z in lo[2]..=hi[2] {
+                for y in lo[1]..=hi[1] {
+                    for x in lo[0]..=hi[0] {
+                        let cell_min = bounds.min + Vec3::new(x as f32, y as f32, z as f32) * cell_size;
+                        let cell = Aabb { min: cell_min, max: cell_min + Vec3::one() * cell_size };
+                        if sphere_overlaps_box(&position, range, &cell) {
+                            cells[(z * dims[1] + y) * dims[0] + x].push(index);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut cell_start = Vec::with_capacity(cells.len() + 1);
+        let mut cell_lights = Vec::new();
+        for cell in &cells {
+            cell_start.push(cell_lights.len());
+            cell_lights.extend_from_slice(cell);
+        }
+        cell_start.push(cell_lights.len());
+
+        LightGrid {
+            origin: bounds.min,
+            cell_size,
+            dims,
+            cell_start,
+            cell_lights,
+            unbounded,
+            inserted,
+        }
+    }
+
+    // Whether every light's sphere of influence still lies inside the one
+    // it was inserted with
+    pub fn covers(&self, lights: &[Light]) -> bool {
+        lights.len() == self.inserted.len()
+            && lights.iter().zip(&self.inserted).all(|(light, inserted)| match (light.position(), inserted) {
+                (Some(position), Some((center, range))) if light.range.is_finite() => {
+                    (position - *center).length() + light.range.max(0.0) <= *range
+                }
+                (position, None) => position.is_none() || !light.range.is_finite(),
+                _ => false,
+            })
+    }
+
+    // Indices of the lights that can reach `point`
+    pub fn query(&self, point: &Vec3) -> impl Iterator<Item = usize> + '_ {
+        let local = *point - self.origin;
+        let inside = !self.cell_lights.is_empty()
+            && local.x >= 0.0 && local.y >= 0.0 && local.z >= 0.0
+            && local.x < self.dims[0] as f32 * self.cell_size
+            && local.y < self.dims[1] as f32 * self.cell_size
+            && local.z < self.dims[2] as f32 * self.cell_size;
+
+        let cell = if inside {
+            let c = cell_coords(&local, self.cell_size, &self.dims);
+            let cell_index = (c[2] * self.dims[1] + c[1]) * self.dims[0] + c[0];
+            &self.cell_lights[self.cell_start[cell_index]..self.cell_start[cell_index + 1]]
+        } else {
+            &[]
+        };
+
+        self.unbounded.iter().chain(cell.iter()).copied()
+    }
+}
+
+fn influence_bounds(position: &Vec3, range: f32) -> Aabb {
+    let extent = Vec3::one() * range;
+    Aabb { min: *position - extent, max: *position + extent }
+}
+
+fn sphere_overlaps_box(center: &Vec3, radius: f32, bounds: &Aabb) -> bool {
+    let closest = Vec3::new(
+        center.x.clamp(bounds.min.x, bounds.max.x),
+        center.y.clamp(bounds.min.y, bounds.max.y),
+        center.z.clamp(bounds.min.z, bounds.max.z),
+    );
+    (closest - *center).length_squared() <= radius * radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::LightShape;
+
+    fn lights() -> Vec<Light> {
+        let mut lights = vec![Light::directional(Vec3::new(0.2, 1.0, 0.1), Vec3::one())];
+        for i in 0..24 {
+            let position = Vec3::new((i * 7 % 13) as f32 - 6.0, (i % 3) as f32, (i * 5 % 11) as f32 - 5.0);
+            let mut light = Light::point(position, Vec3::one() * (0.5 + (i % 4) as f32));
+            light.linear_attenuation = 0.1;
+            light.quadratic_attenuation = 0.05 * (1 + i % 3) as f32;
+            light.update_range(1.0);
+            lights.push(light);
+        }
+        lights
+    }
+
+    fn reaches(light: &Light, point: &Vec3) -> bool {
+        match light.position() {
+            Some(position) if light.range.is_finite() => (position - *point).length() <= light.range,
+            _ => true,
+        }
+    }
+
+    #[test]
+    fn query_matches_brute_force() {
+        let lights = lights();
+        let grid = LightGrid::build(&lights);
+
+        for x in -10..=10 {
+            for z in -10..=10 {
+                let point = Vec3::new(x as f32 * 0.7, 0.5, z as f32 * 0.7);
+                let found: Vec<usize> = grid.query(&point).collect();
+                for (index, light) in lights.iter().enumerate() {
+                    if reaches(light, &point) {
+                        assert!(found.contains(&index), "light {} missing at {:?}", index, point);
+                    }
+                }
+                for index in found {
+                    let light = &lights[index];
+                    if let (Some(position), true) = (light.position(), light.range.is_finite()) {
+                        let padded = light.range * RANGE_SLACK + RANGE_MARGIN;
+                        assert!((position - point).length() <= padded + grid.cell_size * 3.0f32.sqrt());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn covers_until_a_light_leaves_its_cells() {
+        let mut lights = lights();
+        let grid = LightGrid::build(&lights);
+        assert!(grid.covers(&lights));
+
+        if let LightShape::Point { position } = &mut lights[3].shape {
+            *position = *position + Vec3::new(0.1, 0.0, 0.0);
+        }
+        assert!(grid.covers(&lights));
+
+        if let LightShape::Point { position } = &mut lights[3].shape {
+            *position = *position + Vec3::new(20.0, 0.0, 0.0);
+        }
+        assert!(!grid.covers(&lights));
+    }
+}
@@ -2,20 +2,42 @@ mod math;
 mod materials;
 mod camera;
 mod scene;
+mod bvh;
+mod voxel_grid;
+mod light;
+mod light_grid;
+mod sky;
+mod fog;
+mod clouds;
+mod irradiance_cache;
 mod framebuffer;
+mod bloom;
+mod taa;
+mod denoise;
+mod tonemap;
+mod color;
 mod raytracer;
+mod sampling;
+mod path_tracer;
+mod filter;
 
-use minifb::{Key, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 use framebuffer::Framebuffer;
-use raytracer::Raytracer;
+use raytracer::{Raytracer, RenderMode};
 use camera::Camera;
-use scene::Scene;
+use scene::{Scene, Accelerator};
+use filter::SampleJitter;
+use bloom::Bloom;
+use taa::Taa;
+use denoise::Denoiser;
 use std::time::Instant;
 
 const WINDOW_WIDTH: usize = 800;
 const WINDOW_HEIGHT: usize = 600;
 const RENDER_WIDTH: usize = 200;
 const RENDER_HEIGHT: usize = 150;
+// Seconds the shutter stays open when motion blur is on
+const MOTION_BLUR_SHUTTER: f32 = 1.0 / 30.0;
 
 fn main() {
     let mut window = Window::new(
@@ -28,6 +50,18 @@ fn main() {
     window.set_target_fps(60);
 
     println!("Controles: WASD + Flechas + Mouse + ESC");
+    println!("B: alternar BVH / grid de voxeles");
+    println!("P: alternar preview / path tracing");
+    println!("T: pausar / reanudar el tiempo (la imagen converge con la cámara quieta)");
+    println!("N: muestras por pixel, F: filtro de reconstrucción, J: tipo de jitter");
+    println!("[ ]: apertura del lente, clic derecho: enfocar el bloque bajo el cursor");
+    println!("M: alternar motion blur");
+    println!("L: muestras por luz de área (sombras suaves)");
+    println!("O: muestras de oclusión ambiental, G: iluminación global (caché de irradiancia)");
+    println!("V: alternar niebla volumétrica, C: alternar nubes");
+    println!("H: alternar bloom, R: alternar anti-aliasing temporal, Q: alternar denoiser");
+    println!("X: operador de tone mapping, - =: exposición, E: exposición automática");
+    println!("Z: tipo de dithering");
 
     let mut framebuffer = Framebuffer::new(RENDER_WIDTH, RENDER_HEIGHT);
     let aspect_ratio = RENDER_WIDTH as f32 / RENDER_HEIGHT as f32;
@@ -35,13 +69,18 @@ fn main() {
     camera.set_target(math::Vec3::new(0.0, 0.0, 0.0));
 
     let mut scene = Scene::new();
-    let raytracer = Raytracer::new();
+    let mut raytracer = Raytracer::new();
+    let mut denoiser = Denoiser::new();
+    let mut taa = Taa::new();
+    raytracer.temporal_jitter = taa.enabled;
+    let mut bloom = Bloom::new();
 
     let mut last_time = Instant::now();
     let mut fps_count = 0;
     let mut fps_start = Instant::now();
 
     let mut last_mouse_pos: Option<(f32, f32)> = None;
+    let mut time_paused = false;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
         let delta = Instant::now().duration_since(last_time).as_secs_f32();
@@ -65,6 +104,145 @@ fn main() {
 
         camera.handle_keyboard_input(forward, backward, left, right, up, down, speed);
 
+        if window.is_key_pressed(Key::B, KeyRepeat::No) {
+            scene.accelerator = match scene.accelerator {
+                Accelerator::Bvh => Accelerator::VoxelGrid,
+                Accelerator::VoxelGrid => Accelerator::Bvh,
+            };
+            println!("Aceleración: {:?}", scene.accelerator);
+        }
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            raytracer.mode = match raytracer.mode {
+                RenderMode::Whitted => RenderMode::PathTraced,
+                RenderMode::PathTraced => RenderMode::Whitted,
+            };
+            println!("Modo de render: {:?}", raytracer.mode);
+        }
+
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            time_paused = !time_paused;
+        }
+
+        if window.is_key_pressed(Key::N, KeyRepeat::No) {
+            raytracer.samples_per_pixel = if raytracer.samples_per_pixel >= 16 { 1 } else { raytracer.samples_per_pixel * 2 };
+            println!("Muestras por pixel: {}", raytracer.samples_per_pixel);
+        }
+
+        if window.is_key_pressed(Key::L, KeyRepeat::No) {
+            raytracer.light_samples = if raytracer.light_samples >= 16 { 1 } else { raytracer.light_samples * 2 };
+            println!("Muestras por luz de área: {}", raytracer.light_samples);
+        }
+
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            raytracer.ao_samples = match raytracer.ao_samples {
+                0 => 1,
+                n if n >= 16 => 0,
+                n => n * 2,
+            };
+            println!("Muestras de oclusión ambiental: {}", raytracer.ao_samples);
+        }
+
+        if window.is_key_pressed(Key::G, KeyRepeat::No) {
+            raytracer.irradiance_cache.enabled = !raytracer.irradiance_cache.enabled;
+            println!("Iluminación global: {}", if raytracer.irradiance_cache.enabled { "activada" } else { "desactivada" });
+        }
+
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            raytracer.filter = raytracer.filter.next();
+            println!("Filtro: {:?}", raytracer.filter);
+        }
+
+        if window.is_key_pressed(Key::J, KeyRepeat::No) {
+            raytracer.jitter = match raytracer.jitter {
+                SampleJitter::Stratified => SampleJitter::BlueNoise,
+                SampleJitter::BlueNoise => SampleJitter::Stratified,
+            };
+            println!("Jitter: {:?}", raytracer.jitter);
+        }
+
+        if window.is_key_pressed(Key::LeftBracket, KeyRepeat::Yes) {
+            camera.aperture = (camera.aperture - 0.05).max(0.0);
+            println!("Apertura: {:.2}", camera.aperture);
+        }
+
+        if window.is_key_pressed(Key::RightBracket, KeyRepeat::Yes) {
+            camera.aperture = (camera.aperture + 0.05).min(1.0);
+            println!("Apertura: {:.2}", camera.aperture);
+        }
+
+        if window.is_key_pressed(Key::V, KeyRepeat::No) {
+            scene.fog.enabled = !scene.fog.enabled;
+            println!("Niebla volumétrica: {}", if scene.fog.enabled { "activada" } else { "desactivada" });
+        }
+
+        if window.is_key_pressed(Key::C, KeyRepeat::No) {
+            scene.clouds.enabled = !scene.clouds.enabled;
+            println!("Nubes: {}", if scene.clouds.enabled { "activadas" } else { "desactivadas" });
+        }
+
+        if window.is_key_pressed(Key::H, KeyRepeat::No) {
+            bloom.enabled = !bloom.enabled;
+            println!("Bloom: {}", if bloom.enabled { "activado" } else { "desactivado" });
+        }
+
+        if window.is_key_pressed(Key::Q, KeyRepeat::No) {
+            denoiser.enabled = !denoiser.enabled;
+            println!("Denoiser: {}", if denoiser.enabled { "activado" } else { "desactivado" });
+        }
+
+        if window.is_key_pressed(Key::R, KeyRepeat::No) {
+            taa.enabled = !taa.enabled;
+            raytracer.temporal_jitter = taa.enabled;
+            println!("TAA: {}", if taa.enabled { "activado" } else { "desactivado" });
+        }
+
+        if window.is_key_pressed(Key::X, KeyRepeat::No) {
+            framebuffer.tone_map = framebuffer.tone_map.next();
+            println!("Tone mapping: {:?}", framebuffer.tone_map);
+        }
+
+        if window.is_key_pressed(Key::Minus, KeyRepeat::Yes) {
+            framebuffer.exposure -= 0.25;
+            println!("Exposición: {:+.2} EV", framebuffer.exposure);
+        }
+
+        if window.is_key_pressed(Key::Equal, KeyRepeat::Yes) {
+            framebuffer.exposure += 0.25;
+            println!("Exposición: {:+.2} EV", framebuffer.exposure);
+        }
+
+        if window.is_key_pressed(Key::E, KeyRepeat::No) {
+            framebuffer.auto_exposure = !framebuffer.auto_exposure;
+            println!("Exposición automática: {}", if framebuffer.auto_exposure { "activada" } else { "desactivada" });
+        }
+
+        if window.is_key_pressed(Key::Z, KeyRepeat::No) {
+            framebuffer.dither = framebuffer.dither.next();
+            println!("Dithering: {:?}", framebuffer.dither);
+        }
+
+        if window.is_key_pressed(Key::M, KeyRepeat::No) {
+            // Trailing shutter: each frame integrates the motion since the previous one
+            camera.shutter_open = if camera.shutter_open < 0.0 { 0.0 } else { -MOTION_BLUR_SHUTTER };
+            scene.set_shutter(camera.shutter_open, camera.shutter_close);
+            println!("Motion blur: {}", if camera.shutter_open < 0.0 { "activado" } else { "desactivado" });
+        }
+
+        // Click to focus: the focus plane goes through the block under the cursor
+        if window.get_mouse_down(minifb::MouseButton::Right) {
+            if let Some((mx, my)) = window.get_mouse_pos(minifb::MouseMode::Clamp) {
+                let (width, height) = window.get_size();
+                let x = mx / width as f32 * RENDER_WIDTH as f32;
+                let y = my / height as f32 * RENDER_HEIGHT as f32;
+                let ray = camera.get_ray(x, RENDER_HEIGHT as f32 - y, RENDER_WIDTH as f32, RENDER_HEIGHT as f32);
+                let hit = scene.intersect(&ray);
+                if hit.hit {
+                    camera.focus_on(&hit.point);
+                }
+            }
+        }
+
         if let Some((mx, my)) = window.get_mouse_pos(minifb::MouseMode::Clamp) {
             if window.get_mouse_down(minifb::MouseButton::Left) {
                 if let Some((last_x, last_y)) = last_mouse_pos {
@@ -78,8 +256,15 @@ fn main() {
             }
         }
 
-        scene.update(delta);
+        camera.update_motion(delta);
+        if !time_paused {
+            scene.update(delta);
+        }
         raytracer.render(&mut framebuffer, &scene, &camera);
+        denoiser.apply(&mut framebuffer);
+        taa.apply(&mut framebuffer, &camera);
+        bloom.apply(&mut framebuffer);
+        framebuffer.update_exposure(delta);
 
         let window_buffer = framebuffer.to_u32_buffer();
         window.update_with_buffer(&window_buffer, RENDER_WIDTH, RENDER_HEIGHT).unwrap();
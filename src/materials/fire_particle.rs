@@ -1,14 +1,17 @@
 use crate::math::Vec3;
+use crate::color::srgb_to_linear;
 use super::{Material, MaterialType};
 
 pub fn create() -> Material {
     Material {
         material_type: MaterialType::FireParticle,
-        albedo: Vec3::new(1.0, 0.6, 0.1),
+        albedo: srgb_to_linear(Vec3::new(1.0, 0.6, 0.1)),
         reflectivity: 0.0,
         transparency: 0.0,
         refractive_index: 1.0,
         emissive: Vec3::new(3.0, 1.5, 0.3),
+        absorption: Vec3::zero(),
+        scattering_color: Vec3::zero(),
     }
 }
 
@@ -1,14 +1,17 @@
 use crate::math::Vec3;
+use crate::color::srgb_to_linear;
 use super::{Material, MaterialType, pixelated_pattern};
 
 pub fn create() -> Material {
     Material {
         material_type: MaterialType::Grass,
-        albedo: Vec3::new(0.25, 0.65, 0.25),
+        albedo: srgb_to_linear(Vec3::new(0.25, 0.65, 0.25)),
         reflectivity: 0.01,
         transparency: 0.0,
         refractive_index: 1.0,
         emissive: Vec3::zero(),
+        absorption: Vec3::zero(),
+        scattering_color: Vec3::zero(),
     }
 }
 
@@ -0,0 +1,16 @@
+use crate::math::Vec3;
+use crate::color::srgb_to_linear;
+use super::{Material, MaterialType};
+
+pub fn create() -> Material {
+    Material {
+        material_type: MaterialType::Lamp,
+        albedo: srgb_to_linear(Vec3::new(1.0, 0.9, 0.7)),
+        reflectivity: 0.0,
+        transparency: 0.0,
+        refractive_index: 1.0,
+        emissive: Vec3::new(2.0, 1.8, 1.4),
+        absorption: Vec3::zero(),
+        scattering_color: Vec3::zero(),
+    }
+}
@@ -1,4 +1,5 @@
 use crate::math::Vec3;
+use crate::color::srgb_to_linear;
 
 pub mod stone;
 pub mod water;
@@ -8,8 +9,10 @@ pub mod obsidian;
 pub mod sun;
 pub mod fire_particle;
 pub mod leaves;
+pub mod stained_glass;
+pub mod lamp;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MaterialType {
     Stone,
     Water,
@@ -19,37 +22,46 @@ pub enum MaterialType {
     Sun,
     FireParticle,
     Leaves,
+    StainedGlass,
+    Lamp,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Material {
     pub material_type: MaterialType,
+    // Linear reflectance; constructors and patterns author it in sRGB
     pub albedo: Vec3,
     pub reflectivity: f32,
     pub transparency: f32,
     pub refractive_index: f32,
     pub emissive: Vec3,
+    // Beer-Lambert coefficients of the volume behind a transparent surface,
+    // per unit of path length, and the color light scattered inside it
+    // takes on
+    pub absorption: Vec3,
+    pub scattering_color: Vec3,
 }
 
 impl Material {
     pub fn get_animated_properties(&self, time: f32, position: &Vec3) -> Material {
         let mut material = *self;
         
+        // Patterns return display-space colors
         match self.material_type {
             MaterialType::Water => {
-                material.albedo = water::get_pattern(position);
+                material.albedo = srgb_to_linear(water::get_pattern(position));
             },
             MaterialType::Grass => {
-                material.albedo = grass::get_pattern(position);
+                material.albedo = srgb_to_linear(grass::get_pattern(position));
             },
             MaterialType::Obsidian => {
-                material.albedo = obsidian::get_pattern(position);
+                material.albedo = srgb_to_linear(obsidian::get_pattern(position));
             },
             MaterialType::Wood => {
-                material.albedo = wood::get_pattern(position);
+                material.albedo = srgb_to_linear(wood::get_pattern(position));
             },
             MaterialType::Leaves => {
-                material.albedo = leaves::get_pattern(position);
+                material.albedo = srgb_to_linear(leaves::get_pattern(position));
             },
             MaterialType::FireParticle => {
                 material.emissive = fire_particle::get_animated_emissive(time, position);
@@ -92,6 +104,30 @@ impl Material {
     pub fn leaves() -> Self {
         leaves::create()
     }
+
+    pub fn stained_glass() -> Self {
+        stained_glass::create()
+    }
+
+    pub fn lamp() -> Self {
+        lamp::create()
+    }
+
+    // Fraction of light that survives `distance` units inside the volume
+    pub fn transmittance(&self, distance: f32) -> Vec3 {
+        Vec3::new(
+            (-self.absorption.x * distance).exp(),
+            (-self.absorption.y * distance).exp(),
+            (-self.absorption.z * distance).exp(),
+        )
+    }
+
+    // Radiance `color` seen through `distance` units of the volume, plus
+    // what the volume scatters towards the viewer from `light`
+    pub fn attenuate(&self, color: Vec3, distance: f32, light: Vec3) -> Vec3 {
+        let transmittance = self.transmittance(distance);
+        color * transmittance + self.scattering_color * light * (Vec3::one() - transmittance)
+    }
 }
 
 // Shared utility functions
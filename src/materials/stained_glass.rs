@@ -0,0 +1,18 @@
+use crate::math::Vec3;
+use crate::color::srgb_to_linear;
+use super::{Material, MaterialType};
+
+pub fn create() -> Material {
+    Material {
+        material_type: MaterialType::StainedGlass,
+        albedo: srgb_to_linear(Vec3::new(0.5, 0.2, 0.6)),
+        reflectivity: 0.0,
+        transparency: 0.95,
+        refractive_index: 1.5,
+        emissive: Vec3::zero(),
+        // Purple tint: a 0.2 thick pane keeps about 55% of the red, 70% of
+        // the blue and 20% of the green
+        absorption: Vec3::new(3.0, 8.0, 1.8),
+        scattering_color: Vec3::new(0.03, 0.0, 0.05),
+    }
+}
@@ -1,13 +1,16 @@
 use crate::math::Vec3;
+use crate::color::srgb_to_linear;
 use super::{Material, MaterialType};
 
 pub fn create() -> Material {
     Material {
         material_type: MaterialType::Stone,
-        albedo: Vec3::new(1.0, 1.0, 1.0),
+        albedo: srgb_to_linear(Vec3::new(1.0, 1.0, 1.0)),
         reflectivity: 0.1,
         transparency: 0.0,
         refractive_index: 1.0,
         emissive: Vec3::zero(),
+        absorption: Vec3::zero(),
+        scattering_color: Vec3::zero(),
     }
 }
@@ -1,14 +1,18 @@
 use crate::math::Vec3;
+use crate::color::srgb_to_linear;
 use super::{Material, MaterialType};
 
 pub fn create() -> Material {
     Material {
         material_type: MaterialType::Water,
-        albedo: Vec3::new(0.2, 0.6, 1.0),
+        albedo: srgb_to_linear(Vec3::new(0.2, 0.6, 1.0)),
         reflectivity: 0.6,
         transparency: 0.8,
         refractive_index: 1.33,
         emissive: Vec3::zero(),
+        // Red goes first, so long paths through the pool turn deep blue
+        absorption: Vec3::new(1.2, 0.35, 0.15),
+        scattering_color: Vec3::new(0.02, 0.12, 0.18),
     }
 }
 
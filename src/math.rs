@@ -1,6 +1,6 @@
 use std::ops::{Add, Sub, Mul, Div};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -116,17 +116,58 @@ impl Div<f32> for Vec3 {
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // Seconds relative to the scene time of the frame being rendered, so
+    // that 0 sees the scene exactly as `Scene::update` left it
+    pub time: f32,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Ray::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Vec3, direction: Vec3, time: f32) -> Self {
         Ray {
             origin,
             direction: direction.normalize(),
+            time,
         }
     }
 
     pub fn at(&self, t: f32) -> Vec3 {
         self.origin + self.direction * t
     }
-}
\ No newline at end of file
+}
+
+// Unpolarized Fresnel reflectance for a dielectric interface. `cos_i` is the
+// cosine between the incoming ray and the normal on its side, `eta` the ratio
+// of refractive indices (incident / transmitted). Returns 1.0 on total
+// internal reflection.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin_t2 = eta * eta * (1.0 - cos_i * cos_i);
+    if sin_t2 >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin_t2).sqrt();
+    let r_parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresnel_at_normal_incidence_on_water() {
+        let reflectance = fresnel_dielectric(1.0, 1.0 / 1.33);
+        assert!((reflectance - 0.02).abs() < 1e-3, "{}", reflectance);
+    }
+
+    #[test]
+    fn fresnel_total_internal_reflection() {
+        assert_eq!(fresnel_dielectric(0.1, 1.33), 1.0);
+    }
+}
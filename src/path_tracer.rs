@@ -0,0 +1,135 @@
+use crate::{
+    math::{Vec3, Ray, fresnel_dielectric},
+    scene::{Scene, HitInfo},
+    materials::Material,
+    sampling::{Rng, cosine_hemisphere},
+};
+
+// Bounce after which Russian roulette may terminate a path
+const ROULETTE_START: i32 = 3;
+
+// Unbiased path tracer: cosine-weighted diffuse bounces, next-event
+// estimation towards one light per diffuse vertex and Russian roulette.
+//
+// Light units follow the Whitted preview: a light's emission times its
+// attenuation is the irradiance scale, so a diffuse vertex receives
+// `albedo * emission * cos * attenuation` from it. Emissive geometry that
+// has a light in `scene.lights` is only counted through next-event
+// estimation after a diffuse bounce, never twice.
+//
+// `hit` is the closest hit of `ray`, which the caller has already found.
+pub fn trace_path(ray: &Ray, hit: HitInfo, scene: &Scene, rng: &mut Rng, max_bounces: i32) -> Vec3 {
+    let mut radiance = Vec3::zero();
+    let mut throughput = Vec3::one();
+    let mut ray = *ray;
+    let mut specular_bounce = true;
+    // Transparent material the path is currently inside, if any
+    let mut medium: Option<Material> = None;
+    let mut hit = hit;
+
+    for bounce in 0..max_bounces {
+        if bounce > 0 {
+            hit = scene.intersect(&ray);
+        }
+
+        // Fog in the open air; the camera segment also gathers light shafts
+        if medium.is_none() {
+            let distance = if hit.hit { hit.distance } else { f32::INFINITY };
+            let (transmittance, in_scattered) = scene.fog.segment(&ray, distance, scene, (bounce == 0).then_some(&mut *rng));
+            radiance = radiance + throughput * in_scattered;
+            throughput = throughput * transmittance;
+        }
+
+        if !hit.hit {
+            // After a diffuse bounce the sun was already sampled as a light
+            radiance = radiance + throughput * scene.get_sky_color(&ray.direction, specular_bounce);
+            break;
+        }
+
+        // Beer-Lambert absorption plus light scattered into the segment
+        if let Some(medium) = medium {
+            radiance = radiance + throughput * medium.attenuate(Vec3::zero(), hit.distance, scene.scattered_light());
+            throughput = throughput * medium.transmittance(hit.distance);
+        }
+
+        if specular_bounce {
+            radiance = radiance + throughput * hit.material.emissive;
+        }
+
+        let entering = ray.direction.dot(&hit.normal) < 0.0;
+        let normal = if entering { hit.normal } else { hit.normal * -1.0 };
+        let lobe = rng.next_f32();
+
+        if lobe < hit.material.transparency {
+            // Dielectric: pick reflection or refraction by Fresnel weight
+            let eta = if entering {
+                1.0 / hit.material.refractive_index
+            } else {
+                hit.material.refractive_index
+            };
+            let cos_i = -ray.direction.dot(&normal);
+            let reflectance = fresnel_dielectric(cos_i, eta);
+
+            ray = match ray.direction.refract(&normal, eta) {
+                Some(refracted_dir) if rng.next_f32() >= reflectance => {
+                    medium = if entering { Some(hit.material) } else { None };
+                    Ray::with_time(hit.point - normal * 0.001, refracted_dir, ray.time)
+                }
+                _ => Ray::with_time(hit.point + normal * 0.001, ray.direction.reflect(&normal), ray.time),
+            };
+            specular_bounce = true;
+        } else if hit.material.transparency <= 0.0 && lobe < hit.material.reflectivity {
+            // Opaque mirror; dielectrics reflect through the Fresnel lobe only
+            ray = Ray::with_time(hit.point + normal * 0.001, ray.direction.reflect(&normal), ray.time);
+            specular_bounce = true;
+        } else {
+            radiance = radiance + throughput * hit.material.albedo * sample_light(scene, &hit.point, &normal, ray.time, rng);
+
+            let direction = cosine_hemisphere(&normal, rng.next_f32(), rng.next_f32());
+            ray = Ray::with_time(hit.point + normal * 0.001, direction, ray.time);
+            throughput = throughput * hit.material.albedo;
+            specular_bounce = false;
+        }
+
+        if bounce >= ROULETTE_START {
+            let survival = throughput.x.max(throughput.y).max(throughput.z).clamp(0.05, 0.95);
+            if rng.next_f32() >= survival {
+                break;
+            }
+            throughput = throughput / survival;
+        }
+    }
+
+    radiance
+}
+
+// Next-event estimation: one light picked uniformly among those that can
+// reach `point` at `time`, weighted by the number of candidates
+pub fn sample_light(scene: &Scene, point: &Vec3, normal: &Vec3, time: f32, rng: &mut Rng) -> Vec3 {
+    let candidates = scene.lights_near(point, time).count();
+    if candidates == 0 {
+        return Vec3::zero();
+    }
+
+    let pick = ((rng.next_f32() * candidates as f32) as usize).min(candidates - 1);
+    let light = match scene.lights_near(point, time).nth(pick) {
+        Some(light) => light,
+        None => return Vec3::zero(),
+    };
+
+    if light.direction_from(point).1 > light.range {
+        return Vec3::zero();
+    }
+
+    let (light_dir, distance) = light.sample_direction(point, rng);
+    let cos_theta = light_dir.dot(normal);
+    if cos_theta <= 0.0 {
+        return Vec3::zero();
+    }
+
+    let shadow_origin = *point + *normal * 0.001;
+    let shadow_ray = Ray::with_time(shadow_origin, light_dir, time);
+    let transmittance = scene.light_transmittance(&shadow_ray, &light, distance);
+
+    light.emission() * transmittance * (cos_theta * light.attenuation(distance, scene.sun_intensity) * candidates as f32)
+}
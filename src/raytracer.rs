@@ -1,167 +1,326 @@
 use crate::{
-    math::{Vec3, Ray},
+    math::{Vec3, Ray, fresnel_dielectric},
     camera::Camera,
-    scene::Scene,
-    framebuffer::Framebuffer,
+    materials::Material,
+    scene::{Scene, HitInfo},
+    framebuffer::{Framebuffer, Features},
+    path_tracer::trace_path,
+    sampling::{Rng, cosine_hemisphere},
+    filter::{ReconstructionFilter, SampleJitter, sample_offsets},
+    fog::Fog,
+    clouds::Clouds,
+    irradiance_cache::IrradianceCache,
 };
 use rayon::prelude::*;
 
+// Image position of a sample in pixels and the radiance it carries
+type Sample = (f32, f32, Vec3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    // Fast Whitted-style preview: direct light plus mirror and refraction
+    Whitted,
+    PathTraced,
+}
+
+// Everything the image depends on besides the scene contents. When it does
+// not change between frames, new samples are averaged into the old ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ViewState {
+    position: Vec3,
+    target: Vec3,
+    up: Vec3,
+    fov: f32,
+    zoom: f32,
+    aperture: f32,
+    focus_distance: f32,
+    shutter: (f32, f32),
+    velocity: Vec3,
+    target_velocity: Vec3,
+    time: f32,
+    fog: Fog,
+    clouds: Clouds,
+    mode: RenderMode,
+    samples_per_pixel: u32,
+    light_samples: u32,
+    ao_samples: u32,
+    ao_radius: f32,
+    global_illumination: bool,
+    jitter: SampleJitter,
+    filter: ReconstructionFilter,
+}
+
 pub struct Raytracer {
     max_depth: i32,
+    pub mode: RenderMode,
+    pub samples_per_pixel: u32,
+    // Shadow rays per area light and shading point in the Whitted preview
+    pub light_samples: u32,
+    // Hemisphere rays per camera hit that darken the ambient term in
+    // corners, and how far they look; 0 samples turns occlusion off
+    pub ao_samples: u32,
+    pub ao_radius: f32,
+    // One-bounce diffuse light per block face, refreshed a little each frame
+    pub irradiance_cache: IrradianceCache,
+    pub jitter: SampleJitter,
+    pub filter: ReconstructionFilter,
+    // Jitter even a lone sample right after a reset, for passes that
+    // resolve sub-pixel detail over frames such as TAA
+    pub temporal_jitter: bool,
+    pub max_bounces: i32,
+    frame: u32,
+    last_view: Option<ViewState>,
 }
 
 impl Raytracer {
     pub fn new() -> Self {
-        Raytracer { max_depth: 3 }
+        Raytracer {
+            max_depth: 3,
+            mode: RenderMode::Whitted,
+            samples_per_pixel: 1,
+            light_samples: 1,
+            ao_samples: 0,
+            ao_radius: 1.0,
+            irradiance_cache: IrradianceCache::new(),
+            jitter: SampleJitter::BlueNoise,
+            filter: ReconstructionFilter::Box,
+            temporal_jitter: false,
+            max_bounces: 8,
+            frame: 0,
+            last_view: None,
+        }
     }
 
-    pub fn render(&self, framebuffer: &mut Framebuffer, scene: &Scene, camera: &Camera) {
-        framebuffer.clear();
+    pub fn render(&mut self, framebuffer: &mut Framebuffer, scene: &Scene, camera: &Camera) {
+        self.frame = self.frame.wrapping_add(1);
+
+        let view = ViewState {
+            position: camera.position,
+            target: camera.target,
+            up: camera.up,
+            fov: camera.fov,
+            zoom: camera.zoom,
+            aperture: camera.aperture,
+            focus_distance: camera.focus_distance,
+            shutter: (camera.shutter_open, camera.shutter_close),
+            velocity: camera.velocity,
+            target_velocity: camera.target_velocity,
+            time: scene.time,
+            fog: scene.fog,
+            clouds: scene.clouds,
+            mode: self.mode,
+            samples_per_pixel: self.samples_per_pixel,
+            light_samples: self.light_samples,
+            ao_samples: self.ao_samples,
+            ao_radius: self.ao_radius,
+            global_illumination: self.irradiance_cache.enabled,
+            jitter: self.jitter,
+            filter: self.filter,
+        };
+        if self.last_view != Some(view) {
+            framebuffer.reset_accumulation();
+            self.last_view = Some(view);
+        }
+
+        if self.mode == RenderMode::Whitted {
+            self.irradiance_cache.update(scene, self.frame);
+        }
+
+        // A lone sample on the first frame after a reset goes through the
+        // pixel center for a clean preview; everything else is jittered so
+        // edges converge as frames accumulate. With temporal jitter the
+        // frame index moves it around the pixel instead.
+        let centered = self.samples_per_pixel <= 1 && framebuffer.accumulated_frames == 0 && !self.temporal_jitter;
+        let (width, height) = (framebuffer.width, framebuffer.height);
 
-        let pixels: Vec<(usize, usize, Vec3)> = (0..framebuffer.height)
+        let rows: Vec<(Vec<Sample>, Vec<Features>)> = (0..height)
             .into_par_iter()
-            .flat_map(|y| {
-                (0..framebuffer.width)
-                    .map(|x| {
-                        let color = self.render_pixel(x, y, framebuffer.width, framebuffer.height, scene, camera);
-                        (x, y, color)
-                    })
-                    .collect::<Vec<_>>()
+            .map(|y| {
+                let mut row = Vec::with_capacity(width * self.samples_per_pixel as usize);
+                let mut row_features = Vec::with_capacity(width);
+                for x in 0..width {
+                    let offsets = if centered {
+                        vec![(0.5, 0.5)]
+                    } else {
+                        sample_offsets(self.jitter, self.samples_per_pixel, x, y, self.frame)
+                    };
+
+                    let mut pixel_features = Vec::with_capacity(offsets.len());
+                    for (sample, (offset_x, offset_y)) in offsets.into_iter().enumerate() {
+                        let (sample_x, sample_y) = (x as f32 + offset_x, y as f32 + offset_y);
+                        let mut rng = Rng::for_pixel(x, y, self.frame, sample as u32);
+                        let (color, features) = self.render_sample(sample_x, sample_y, width, height, &mut rng, scene, camera);
+                        row.push((sample_x, sample_y, color));
+                        pixel_features.push(features);
+                    }
+                    row_features.push(Features::average(&pixel_features));
+                }
+                (row, row_features)
             })
             .collect();
 
-        for (x, y, color) in pixels {
-            framebuffer.set_pixel(x, y, color);
+        let mut samples = Vec::with_capacity(width * height * self.samples_per_pixel as usize);
+        let mut features = Vec::with_capacity(width * height);
+        for (row, row_features) in rows {
+            samples.extend(row);
+            features.extend(row_features);
         }
+
+        framebuffer.accumulate_samples(&samples, &self.filter);
+        framebuffer.accumulate_features(&features);
     }
 
-    fn render_pixel(
+    // Radiance along the camera ray through image position `sample_x`,
+    // `sample_y`, measured in pixels from the top-left corner, and the
+    // features of the first thing it hits
+    #[allow(clippy::too_many_arguments)]
+    fn render_sample(
         &self,
-        x: usize,
-        y: usize,
+        sample_x: f32,
+        sample_y: f32,
         width: usize,
         height: usize,
+        rng: &mut Rng,
         scene: &Scene,
         camera: &Camera,
-    ) -> Vec3 {
-        let ray = camera.get_ray(
-            x as f32 + 0.5,
-            (height - 1 - y) as f32 + 0.5,
+    ) -> (Vec3, Features) {
+        let ray = camera.get_lens_ray(
+            sample_x,
+            height as f32 - sample_y,
             width as f32,
             height as f32,
+            rng.next_f32(),
+            rng.next_f32(),
+            camera.shutter_time(rng.next_f32()),
         );
 
-        self.trace_ray(&ray, self.max_depth, scene)
+        let hit = scene.intersect(&ray);
+        let color = match self.mode {
+            RenderMode::Whitted => self.shade(&ray, &hit, self.max_depth, None, scene, rng),
+            RenderMode::PathTraced => trace_path(&ray, hit, scene, rng, self.max_bounces),
+        };
+        (color, Features::from_hit(&hit))
     }
 
-    fn trace_ray(&self, ray: &Ray, depth: i32, scene: &Scene) -> Vec3 {
+    // `medium` is the transparent material the ray travels through, if any
+    fn trace_ray(&self, ray: &Ray, depth: i32, medium: Option<&Material>, scene: &Scene, rng: &mut Rng) -> Vec3 {
         if depth <= 0 {
             return Vec3::zero();
         }
 
-        let hit = scene.intersect(ray);
+        self.shade(ray, &scene.intersect(ray), depth, medium, scene, rng)
+    }
+
+    // Radiance along `ray`, whose closest hit is `hit`
+    fn shade(&self, ray: &Ray, hit: &HitInfo, depth: i32, medium: Option<&Material>, scene: &Scene, rng: &mut Rng) -> Vec3 {
+        // Only camera rays march the fog for light shafts; deeper rays and
+        // rays inside water or glass just see it attenuate
+        let march = depth == self.max_depth && medium.is_none();
         if !hit.hit {
-            return scene.get_sky_color(&ray.direction);
+            let sky = scene.get_sky_color(&ray.direction, true);
+            return match medium {
+                Some(_) => sky,
+                None => scene.fog.apply(ray, f32::INFINITY, sky, scene, march.then_some(rng)),
+            };
         }
 
-        let mut color = scene.ambient_light * hit.material.albedo;
-
-        // Direct lighting from sun
-        let light_dir = (scene.sun_position - hit.point).normalize();
-        let shadow_ray = Ray::new(hit.point + hit.normal * 0.001, light_dir);
-        let shadow_hit = scene.intersect(&shadow_ray);
-
-        if !shadow_hit.hit || shadow_hit.distance > (scene.sun_position - hit.point).length() {
-            let light_intensity = light_dir.dot(&hit.normal).max(0.0) * scene.sun_intensity;
-            color = color + hit.material.albedo * light_intensity * Vec3::new(1.0, 0.9, 0.7);
-        }
+        // Indirect diffuse from the irradiance cache, which already sees the
+        // nearby geometry, or the flat ambient term darkened in corners
+        let mut color = match self.irradiance_cache.lookup(&hit.point, &hit.normal) {
+            Some(irradiance) => irradiance * hit.material.albedo,
+            None => {
+                let occlusion = if march { self.ambient_occlusion(&hit.point, &hit.normal, ray.time, scene, rng) } else { 1.0 };
+                scene.ambient_light * hit.material.albedo * occlusion
+            }
+        };
 
-        for cube in &scene.cubes {
-            if cube.material.emissive.length() > 0.0 {
-                let light_pos = cube.center;
-                let distance = (light_pos - hit.point).length();
-                
-                if distance > 15.0 {
-                    continue;
-                }
-                
-                let light_dir = (light_pos - hit.point).normalize();
-                let base_att = 1.0 / (1.0 + 0.05 * distance + 0.005 * distance * distance);
-                let night_boost = 1.0 + (1.0 - scene.sun_intensity) * 0.8;
-                let attenuation = base_att * night_boost;
-
-                let shadow_ray = Ray::new(hit.point + hit.normal * 0.001, light_dir);
-                let shadow_hit = scene.intersect(&shadow_ray);
-
-                if !shadow_hit.hit || shadow_hit.distance > distance {
-                    let light_intensity = light_dir.dot(&hit.normal).max(0.0) * attenuation;
-                    let animated_material = cube.material.get_animated_properties(scene.time, &cube.center);
-                    color = color + hit.material.albedo * animated_material.emissive * light_intensity;
-                }
+        // Direct lighting from the sun, torches and emissive geometry. Area
+        // lights are sampled at random points for soft shadows.
+        let shadow_origin = hit.point + hit.normal * 0.001;
+        for light in scene.lights_near(&hit.point, ray.time) {
+            if light.direction_from(&hit.point).1 > light.range || light.emission() == Vec3::zero() {
+                continue;
             }
-        }
 
-        // Emissive lighting from spheres (fire particles)
-        for sphere in &scene.spheres {
-            if sphere.material.emissive.length() > 0.0 {
-                let light_pos = sphere.center;
-                let distance = (light_pos - hit.point).length();
-                
-                // Skip if light is too far
-                if distance > 12.0 {
+            let samples = if light.is_area() { self.light_samples.max(1) } else { 1 };
+            let mut irradiance = Vec3::zero();
+            for _ in 0..samples {
+                let (light_dir, distance) = light.sample_direction(&hit.point, rng);
+                let ndotl = light_dir.dot(&hit.normal);
+                if ndotl <= 0.0 {
                     continue;
                 }
-                
-                let light_dir = (light_pos - hit.point).normalize();
 
-                // Enhanced attenuation for sphere lights with night boost
-                let base_attenuation = 1.0 / (1.0 + 0.08 * distance + 0.008 * distance * distance);
-                let night_boost = 1.0 + (1.0 - scene.sun_intensity) * 1.2;
-                let attenuation = base_attenuation * night_boost;
-
-                let shadow_ray = Ray::new(hit.point + hit.normal * 0.001, light_dir);
-                let shadow_hit = scene.intersect(&shadow_ray);
-
-                if !shadow_hit.hit || shadow_hit.distance > distance {
-                    let light_intensity = light_dir.dot(&hit.normal).max(0.0) * attenuation;
-                    let animated_material = sphere.material.get_animated_properties(scene.time, &sphere.center);
-                    color = color + hit.material.albedo * animated_material.emissive * light_intensity;
-                }
+                let shadow_ray = Ray::with_time(shadow_origin, light_dir, ray.time);
+                let transmittance = scene.light_transmittance(&shadow_ray, &light, distance);
+                irradiance = irradiance + transmittance * (ndotl * light.attenuation(distance, scene.sun_intensity));
             }
+
+            color = color + hit.material.albedo * light.emission() * irradiance / samples as f32;
         }
 
         // Add emissive contribution
         color = color + hit.material.emissive;
 
-        // Reflection
-        if hit.material.reflectivity > 0.0 {
+        // Mirror reflection of opaque surfaces. Dielectrics reflect only
+        // through the Fresnel term below, so water is not also blended
+        // towards a fixed, angle-independent mirror.
+        if hit.material.reflectivity > 0.0 && hit.material.transparency <= 0.0 {
             let reflected_dir = ray.direction.reflect(&hit.normal);
-            let reflected_ray = Ray::new(hit.point + hit.normal * 0.001, reflected_dir);
-            let reflected_color = self.trace_ray(&reflected_ray, depth - 1, scene);
+            let reflected_ray = Ray::with_time(hit.point + hit.normal * 0.001, reflected_dir, ray.time);
+            let reflected_color = self.trace_ray(&reflected_ray, depth - 1, medium, scene, rng);
             color = color.lerp(&reflected_color, hit.material.reflectivity);
         }
 
-        // Refraction
+        // Dielectric part: reflection and refraction weighted by Fresnel, so
+        // water turns into a mirror at grazing angles. Under total internal
+        // reflection everything goes to the reflected ray.
         if hit.material.transparency > 0.0 {
-            let eta = if ray.direction.dot(&hit.normal) < 0.0 {
+            let entering = ray.direction.dot(&hit.normal) < 0.0;
+            let normal = if entering { hit.normal } else { hit.normal * -1.0 };
+            let eta = if entering {
                 1.0 / hit.material.refractive_index
             } else {
                 hit.material.refractive_index
             };
+            let reflectance = fresnel_dielectric(-ray.direction.dot(&normal), eta);
 
-            let normal = if ray.direction.dot(&hit.normal) < 0.0 {
-                hit.normal
-            } else {
-                hit.normal * -1.0
-            };
+            let reflected_ray = Ray::with_time(hit.point + normal * 0.001, ray.direction.reflect(&normal), ray.time);
+            let mut dielectric = self.trace_ray(&reflected_ray, depth - 1, medium, scene, rng) * reflectance;
 
             if let Some(refracted_dir) = ray.direction.refract(&normal, eta) {
-                let refracted_ray = Ray::new(hit.point - normal * 0.001, refracted_dir);
-                let refracted_color = self.trace_ray(&refracted_ray, depth - 1, scene);
-                color = color.lerp(&refracted_color, hit.material.transparency);
+                if reflectance < 1.0 {
+                    let refracted_ray = Ray::with_time(hit.point - normal * 0.001, refracted_dir, ray.time);
+                    let inside = if entering { Some(&hit.material) } else { None };
+                    let refracted_color = self.trace_ray(&refracted_ray, depth - 1, inside, scene, rng);
+                    dielectric = dielectric + refracted_color * (1.0 - reflectance);
+                }
             }
+
+            color = color.lerp(&dielectric, hit.material.transparency);
         }
 
-        color
+        // Beer-Lambert absorption along the segment inside the volume, or
+        // fog in the open air
+        match medium {
+            Some(medium) => medium.attenuate(color, hit.distance, scene.scattered_light()),
+            None => scene.fog.apply(ray, hit.distance, color, scene, march.then_some(rng)),
+        }
+    }
+
+    // Fraction of short cosine-weighted rays around `normal` that escape
+    fn ambient_occlusion(&self, point: &Vec3, normal: &Vec3, time: f32, scene: &Scene, rng: &mut Rng) -> f32 {
+        if self.ao_samples == 0 {
+            return 1.0;
+        }
+
+        let origin = *point + *normal * 0.001;
+        let open = (0..self.ao_samples)
+            .filter(|_| {
+                let direction = cosine_hemisphere(normal, rng.next_f32(), rng.next_f32());
+                !scene.occluded(&Ray::with_time(origin, direction, time), self.ao_radius)
+            })
+            .count();
+        open as f32 / self.ao_samples as f32
     }
 }
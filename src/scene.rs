@@ -1,5 +1,32 @@
 use crate::math::{Vec3, Ray};
-use crate::materials::Material;
+use crate::materials::{Material, MaterialType};
+use crate::bvh::{Aabb, Bvh, Primitive};
+use crate::voxel_grid::VoxelGrid;
+use crate::light::{Light, LightShape};
+use crate::light_grid::LightGrid;
+use crate::fog::Fog;
+use crate::clouds::Clouds;
+use crate::sky::{Sky, moon_disk, moon_illumination, starfield};
+
+const VOXEL_CELL_SIZE: f32 = 1.0;
+const SUN_COLOR: Vec3 = Vec3 { x: 1.0, y: 0.9, z: 0.7 };
+const MOONLIGHT_COLOR: Vec3 = Vec3 { x: 0.6, y: 0.7, z: 1.0 };
+const MOONLIGHT_INTENSITY: f32 = 0.25;
+// Angle the sun travels per second of scene time
+const DAY_CYCLE_RATE: f32 = 0.2;
+// Seconds from one full moon to the next, eight day-night cycles
+const LUNAR_PERIOD: f32 = 8.0 * 2.0 * std::f32::consts::PI / DAY_CYCLE_RATE;
+const TORCH_LIGHT_INTENSITY: f32 = 0.3;
+// Samples along the shutter used to bound a moving sphere
+const MOTION_BOUNDS_STEPS: usize = 8;
+// Transparent surfaces a shadow ray may cross before it counts as blocked
+const MAX_SHADOW_LAYERS: usize = 8;
+// Gap below which leaving one block and entering another of the same
+// material counts as staying inside one volume
+const ADJACENT_FACE_GAP: f32 = 0.01;
+// Longest path through a medium a shadow ray accounts for when it runs out
+// of layers inside it, so lights at infinity do not black it out
+const MAX_SHADOW_MEDIUM_DISTANCE: f32 = 16.0;
 
 #[derive(Debug, Clone, Copy)]
 pub struct HitInfo {
@@ -41,7 +68,8 @@ impl Sphere {
         Sphere { center, radius, material }
     }
 
-    pub fn intersect(&self, ray: &Ray, time: f32) -> HitInfo {
+    // Distance to the nearest hit, without evaluating the material
+    pub fn hit_distance(&self, ray: &Ray) -> Option<f32> {
         let oc = ray.origin - self.center;
         let a = ray.direction.dot(&ray.direction);
         let b = 2.0 * oc.dot(&ray.direction);
@@ -49,14 +77,27 @@ impl Sphere {
         let discriminant = b * b - 4.0 * a * c;
 
         if discriminant < 0.0 {
-            return HitInfo::miss();
+            return None;
         }
 
         let sqrt_discriminant = discriminant.sqrt();
         let t1 = (-b - sqrt_discriminant) / (2.0 * a);
         let t2 = (-b + sqrt_discriminant) / (2.0 * a);
 
-        let t = if t1 > 0.001 { t1 } else if t2 > 0.001 { t2 } else { return HitInfo::miss(); };
+        if t1 > 0.001 {
+            Some(t1)
+        } else if t2 > 0.001 {
+            Some(t2)
+        } else {
+            None
+        }
+    }
+
+    pub fn intersect(&self, ray: &Ray, time: f32) -> HitInfo {
+        let t = match self.hit_distance(ray) {
+            Some(t) => t,
+            None => return HitInfo::miss(),
+        };
 
         let hit_point = ray.at(t);
         let normal = (hit_point - self.center).normalize();
@@ -76,7 +117,8 @@ impl Cube {
         Cube { center, size, material }
     }
 
-    pub fn intersect(&self, ray: &Ray, time: f32) -> HitInfo {
+    // Distance to the nearest hit, without evaluating the material
+    pub fn hit_distance(&self, ray: &Ray) -> Option<f32> {
         let min = self.center - self.size * 0.5;
         let max = self.center + self.size * 0.5;
 
@@ -93,14 +135,26 @@ impl Cube {
         let tmax = t1.max(t2).min(t3.max(t4)).min(t5.max(t6));
 
         if tmax < 0.0 || tmin > tmax {
-            return HitInfo::miss();
+            return None;
         }
 
         let t = if tmin > 0.001 { tmin } else { tmax };
         if t < 0.001 {
-            return HitInfo::miss();
+            return None;
         }
 
+        Some(t)
+    }
+
+    pub fn intersect(&self, ray: &Ray, time: f32) -> HitInfo {
+        let min = self.center - self.size * 0.5;
+        let max = self.center + self.size * 0.5;
+
+        let t = match self.hit_distance(ray) {
+            Some(t) => t,
+            None => return HitInfo::miss(),
+        };
+
         let hit_point = ray.at(t);
         let mut normal = Vec3::zero();
 
@@ -130,6 +184,12 @@ impl Cube {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Accelerator {
+    Bvh,
+    VoxelGrid,
+}
+
 #[derive(Debug, Clone)]
 pub struct Scene {
     pub cubes: Vec<Cube>,
@@ -138,8 +198,24 @@ pub struct Scene {
     pub sun_position: Vec3,
     pub sun_intensity: f32,
     pub ambient_light: Vec3,
-    pub torch_positions: Vec<Vec3>,
+    // Haziness of the analytic sky, from 2 (clear) to about 10
+    pub turbidity: f32,
+    pub fog: Fog,
+    pub clouds: Clouds,
+    sky: Sky,
+    pub lights: Vec<Light>,
+    pub sun_light: usize,
+    pub sun_sphere: usize,
+    // Direction towards the moon and its phase, 0 new and 0.5 full
+    pub moon_direction: Vec3,
+    pub moon_phase: f32,
+    pub moon_light: usize,
     pub fire_particle_start_index: usize,
+    pub accelerator: Accelerator,
+    shutter: (f32, f32),
+    bvh: Bvh,
+    voxel_grid: VoxelGrid,
+    light_grid: LightGrid,
 }
 
 impl Scene {
@@ -151,14 +227,32 @@ impl Scene {
             sun_position: Vec3::new(10.0, 10.0, 10.0),
             sun_intensity: 1.0,
             ambient_light: Vec3::new(0.3, 0.4, 0.6),
-            torch_positions: Vec::new(),
+            turbidity: 3.0,
+            fog: Fog::new(),
+            clouds: Clouds::new(),
+            sky: Sky::new(Vec3::new(10.0, 10.0, 10.0), 3.0),
+            lights: Vec::new(),
+            sun_light: 0,
+            sun_sphere: 0,
+            moon_direction: Vec3::new(-1.0, -1.0, -1.0).normalize(),
+            moon_phase: 0.5,
+            moon_light: 0,
             fire_particle_start_index: 0,
+            accelerator: Accelerator::Bvh,
+            shutter: (0.0, 0.0),
+            bvh: Bvh::build(&[], &[]),
+            voxel_grid: VoxelGrid::build(&[], &[], VOXEL_CELL_SIZE),
+            light_grid: LightGrid::build(&[]),
         };
 
         scene.create_minecraft_world();
+        scene.collect_lights();
+        scene.bvh = Bvh::build(&scene.cubes, &scene.spheres);
+        scene.voxel_grid = VoxelGrid::build(&scene.cubes, &scene.spheres, VOXEL_CELL_SIZE);
         scene
     }
 
+    #[allow(clippy::manual_range_contains)]
     fn is_water_position(&self, x: i32, z: i32) -> bool {
         let main = (x >= 2 && x <= 5 && z >= 3 && z <= 5) ||
                    (x >= 3 && x <= 4 && z >= 2 && z <= 6) ||
@@ -258,9 +352,18 @@ impl Scene {
                     Material::obsidian(),
                 ));
             }
+
+            // Stained glass pane filling the opening
+            self.cubes.push(Cube::new(
+                Vec3::new(*portal_x, 2.0, portal1_z),
+                Vec3::new(1.0, 3.0, 0.2),
+                Material::stained_glass(),
+            ));
         }
 
-        // Add a sun sphere in the sky
+        // Add a sun sphere in the sky; `update` moves it along with the sun
+        // light, and its radius sets how soft the sun's shadows are
+        self.sun_sphere = self.spheres.len();
         self.spheres.push(Sphere::new(
             Vec3::new(15.0, 20.0, 10.0),
             2.0,
@@ -391,23 +494,132 @@ impl Scene {
             Vec3::new(1.0, 0.8, 1.0),
             Material::leaves(),
         ));
-        
-        // No torch positions - torches have been removed
-        self.torch_positions = vec![];
+
+        // A torch in front of each portal
+        self.add_torch(portal1_x, 1.5);
+        self.add_torch(portal2_x, 1.5);
+
+        // Lamp panel set in the grass between the portals
+        self.cubes.push(Cube::new(
+            Vec3::new(0.0, -0.58, 3.0),
+            Vec3::new(1.0, 0.04, 1.0),
+            Material::lamp(),
+        ));
+    }
+
+    fn add_torch(&mut self, x: f32, z: f32) {
+        // Wooden post standing on the grass, with a flame block on top
+        self.cubes.push(Cube::new(
+            Vec3::new(x, -0.3, z),
+            Vec3::new(0.15, 0.6, 0.15),
+            Material::wood(),
+        ));
+        self.cubes.push(Cube::new(
+            Vec3::new(x, 0.09, z),
+            Vec3::new(0.18, 0.18, 0.18),
+            Material::fire_particle(),
+        ));
+    }
+
+    // Builds the light list from the sun and every emissive block or sphere
+    fn collect_lights(&mut self) {
+        self.lights.clear();
+
+        self.sun_light = self.lights.len();
+        let sun_radius = self.spheres[self.sun_sphere].radius;
+        self.lights.push(Light::sphere(self.sun_position, sun_radius, sunlight(&self.sun_position, self.sun_intensity)));
+
+        self.moon_light = self.lights.len();
+        self.lights.push(Light::directional(self.moon_direction, moonlight(&self.moon_direction, self.moon_phase)));
+
+        for (index, cube) in self.cubes.iter().enumerate() {
+            if cube.material.emissive.length() > 0.0 {
+                let light = match cube.material.material_type {
+                    MaterialType::FireParticle => Light {
+                        intensity: TORCH_LIGHT_INTENSITY,
+                        ..Light::emissive_cube(index, cube)
+                    },
+                    MaterialType::Lamp => Light::emissive_panel(index, cube),
+                    _ => Light::emissive_cube(index, cube),
+                };
+                self.lights.push(light);
+            }
+        }
+
+        for (index, sphere) in self.spheres.iter().enumerate() {
+            // The sun body is already covered by the sun light, and was
+            // always out of reach of the old per-sphere distance cutoff
+            if matches!(sphere.material.material_type, MaterialType::Sun) {
+                continue;
+            }
+
+            if sphere.material.emissive.length() > 0.0 {
+                self.lights.push(Light::emissive_sphere(index, sphere));
+            }
+        }
+
+        self.sync_lights();
+    }
+
+    // Copies the current sun state and the position, size and animated
+    // emission of the geometry each light follows. The light grid is rebuilt
+    // only once a light has moved or grown out of its padded cells.
+    fn sync_lights(&mut self) {
+        let sun = &mut self.lights[self.sun_light];
+        sun.shape = LightShape::Sphere { center: self.sun_position, radius: self.spheres[self.sun_sphere].radius };
+        sun.color = sunlight(&self.sun_position, self.sun_intensity);
+
+        let moon = &mut self.lights[self.moon_light];
+        moon.shape = LightShape::Directional { direction: self.moon_direction };
+        moon.color = moonlight(&self.moon_direction, self.moon_phase);
+
+        for light in &mut self.lights {
+            match light.source {
+                Some(Primitive::Cube(index)) => {
+                    let cube = &self.cubes[index];
+                    light.shape = match light.shape {
+                        LightShape::Rect { .. } => Light::panel_shape(cube),
+                        _ => LightShape::Box { center: cube.center, size: cube.size },
+                    };
+                    light.color = cube.material.get_animated_properties(self.time, &cube.center).emissive;
+                }
+                Some(Primitive::Sphere(index)) => {
+                    let sphere = &self.spheres[index];
+                    light.shape = LightShape::Sphere { center: sphere.center, radius: sphere.radius };
+                    light.color = sphere.material.get_animated_properties(self.time, &sphere.center).emissive;
+                }
+                None => {}
+            }
+            light.update_range(self.sun_intensity);
+        }
+
+        if !self.light_grid.covers(&self.lights) {
+            self.light_grid = LightGrid::build(&self.lights);
+        }
+    }
+
+    // Lights that can reach `point`, evaluated `time_offset` seconds away
+    // from the current time
+    pub fn lights_near(&self, point: &Vec3, time_offset: f32) -> impl Iterator<Item = Light> + '_ {
+        self.light_grid.query(point).map(move |index| self.light_at(index, time_offset))
     }
 
     pub fn update(&mut self, delta_time: f32) {
         self.time += delta_time;
         
         // Update sun position for day-night cycle
-        let day_cycle = self.time * 0.2; 
-        self.sun_position = Vec3::new(
-            day_cycle.cos() * 15.0,
-            (day_cycle.sin() * 10.0).max(2.0),
-            10.0,
-        );
-        
-        self.sun_intensity = (day_cycle.sin().max(0.0) * 0.8 + 0.2).max(0.1);
+        let (sun_position, sun_intensity) = sun_at(self.time);
+        self.sun_position = sun_position;
+        self.sun_intensity = sun_intensity;
+        self.spheres[self.sun_sphere].center = sun_position;
+        // The body dims with the daylight so it does not glow under the
+        // world or in reflections at night
+        self.spheres[self.sun_sphere].material.emissive = Material::sun().emissive * daylight(sun_position.normalize().y);
+        self.sky = Sky::new(sun_position, self.turbidity);
+
+        let (moon_direction, moon_phase) = moon_at(self.time);
+        self.moon_direction = moon_direction;
+        self.moon_phase = moon_phase;
         
         let night_factor = (1.0 - self.sun_intensity).max(0.0);
         self.ambient_light = Vec3::new(
@@ -417,84 +629,417 @@ impl Scene {
         );
         
         // Animate fire particles like torch rays
-        let campfire_x = -6.0;
-        let campfire_z = -6.0;
-        
         for i in self.fire_particle_start_index..self.spheres.len() {
-            let particle_index = i - self.fire_particle_start_index;
-            
-            if particle_index < 8 {
-                // Outer fire rays - move them in arcs and upward
-                let base_angle = (particle_index as f32) * 0.785; 
-                let time_offset = particle_index as f32 * 0.3; 
-                let wave_time = self.time * 3.0 + time_offset;
-                
-                // Create swaying motion like torch flames
-                let sway_x = (wave_time * 1.2).sin() * 0.2;
-                let sway_z = (wave_time * 0.8).cos() * 0.15;
-                let rise = (wave_time * 2.0).sin().abs() * 0.5 + 0.3; 
-                
-                // Reset particle position if it gets too high 
-                let height = if rise > 1.0 { 0.1 } else { rise };
-                
-                let distance = 0.2 + (wave_time * 0.5).sin().abs() * 0.3;
-                
-                self.spheres[i].center = Vec3::new(
-                    campfire_x + (base_angle + sway_x).cos() * distance,
-                    height,
-                    campfire_z + (base_angle + sway_z).sin() * distance
-                );
-                
-                // Vary radius for flickering effect
-                self.spheres[i].radius = 0.06 + (wave_time * 4.0).sin().abs() * 0.04;
-                
-            } else {
-                // Central flame particles - vertical motion with wobble
-                let central_index = particle_index - 8;
-                let wobble_time = self.time * 4.0 + central_index as f32 * 0.5;
-                
-                let wobble_x = (wobble_time * 1.5).sin() * 0.08;
-                let wobble_z = (wobble_time * 1.8).cos() * 0.06;
-                let flicker_height = (wobble_time * 2.5).sin().abs() * 0.2 + central_index as f32 * 0.12;
-                
-                // Reset if too high
-                let height = if flicker_height > 0.8 { 0.0 } else { flicker_height };
-                
-                self.spheres[i].center = Vec3::new(
-                    campfire_x + wobble_x,
-                    height,
-                    campfire_z + wobble_z
-                );
-                
-                self.spheres[i].radius = 0.08 + (0.8 - height) * 0.05 + (wobble_time * 6.0).sin().abs() * 0.02;
+            let (center, radius) = fire_particle_at(i - self.fire_particle_start_index, self.time);
+            self.spheres[i].center = center;
+            self.spheres[i].radius = radius;
+        }
+
+        // Fire particles moved, so only the bounds need updating. The voxel
+        // grid keeps spheres in its fallback list and needs no update.
+        self.refit_bvh();
+        self.sync_lights();
+    }
+
+    // Interval around `time`, in seconds, that camera rays may sample. The
+    // BVH bounds moving spheres over the whole interval.
+    pub fn set_shutter(&mut self, open: f32, close: f32) {
+        self.shutter = (open.min(close), open.max(close));
+        self.refit_bvh();
+    }
+
+    fn refit_bvh(&mut self) {
+        let (cubes, spheres) = (&self.cubes, &self.spheres);
+        let fire_particle_start_index = self.fire_particle_start_index;
+        let (open, close) = self.shutter;
+        let time = self.time;
+
+        self.bvh.refit(|primitive| match primitive {
+            Primitive::Sphere(index) if index >= fire_particle_start_index && open < close => {
+                // Particles follow curved paths, so sample them densely and
+                // pad by the largest step between samples
+                let mut bounds = Aabb::empty();
+                let mut previous: Option<Vec3> = None;
+                let mut padding: f32 = 0.0;
+                for step in 0..=MOTION_BOUNDS_STEPS {
+                    let t = open + (close - open) * step as f32 / MOTION_BOUNDS_STEPS as f32;
+                    let (center, radius) = fire_particle_at(index - fire_particle_start_index, time + t);
+                    bounds = bounds.union(&Aabb::from_sphere(&Sphere::new(center, radius, spheres[index].material)));
+                    if let Some(previous) = previous {
+                        padding = padding.max((center - previous).length() * 0.5);
+                    }
+                    previous = Some(center);
+                }
+                let padding = Vec3::one() * padding;
+                Aabb { min: bounds.min - padding, max: bounds.max + padding }
             }
+            _ => primitive.bounds(cubes, spheres),
+        });
+    }
+
+    // The sphere as it is `time_offset` seconds away from the current time
+    fn sphere_at(&self, index: usize, time_offset: f32) -> Sphere {
+        let mut sphere = self.spheres[index];
+        if time_offset != 0.0 && index >= self.fire_particle_start_index {
+            let (center, radius) = fire_particle_at(index - self.fire_particle_start_index, self.time + time_offset);
+            sphere.center = center;
+            sphere.radius = radius;
         }
+        sphere
+    }
+
+    // A light as it is `time_offset` seconds away from the current time.
+    // Ranges are kept, so lights that move far within the shutter may be
+    // missed by the light grid at the ends of the interval.
+    fn light_at(&self, index: usize, time_offset: f32) -> Light {
+        let mut light = self.lights[index];
+        if time_offset == 0.0 {
+            return light;
+        }
+
+        let time = self.time + time_offset;
+        if index == self.sun_light {
+            let (position, intensity) = sun_at(time);
+            light.shape = LightShape::Sphere { center: position, radius: self.spheres[self.sun_sphere].radius };
+            light.color = sunlight(&position, intensity);
+        } else if index == self.moon_light {
+            let (direction, phase) = moon_at(time);
+            light.shape = LightShape::Directional { direction };
+            light.color = moonlight(&direction, phase);
+        }
+
+        match light.source {
+            Some(Primitive::Cube(index)) => {
+                let cube = &self.cubes[index];
+                light.color = cube.material.get_animated_properties(time, &cube.center).emissive;
+            }
+            Some(Primitive::Sphere(index)) => {
+                let sphere = self.sphere_at(index, time_offset);
+                light.shape = LightShape::Sphere { center: sphere.center, radius: sphere.radius };
+                light.color = sphere.material.get_animated_properties(time, &sphere.center).emissive;
+            }
+            None => {}
+        }
+        light
     }
 
     pub fn intersect(&self, ray: &Ray) -> HitInfo {
-        let mut closest_hit = HitInfo::miss();
-        
-        for cube in &self.cubes {
-            let hit = cube.intersect(ray, self.time);
-            if hit.hit && hit.distance < closest_hit.distance {
-                closest_hit = hit;
+        let time = self.time + ray.time;
+        let intersect_primitive = |primitive| match primitive {
+            Primitive::Cube(index) => self.cubes[index].intersect(ray, time),
+            Primitive::Sphere(index) => self.sphere_at(index, ray.time).intersect(ray, time),
+        };
+
+        match self.accelerator {
+            Accelerator::Bvh => self.bvh.intersect(ray, intersect_primitive),
+            Accelerator::VoxelGrid => self.voxel_grid.intersect(ray, intersect_primitive),
+        }
+    }
+
+    // Any-hit query for shadow rays: true as soon as something opaque
+    // blocks the ray closer than `max_t`. Materials are never evaluated.
+    pub fn occluded(&self, ray: &Ray, max_t: f32) -> bool {
+        self.any_hit(ray, max_t, false)
+    }
+
+    // Any-hit query over only the opaque or only the transparent primitives
+    fn any_hit(&self, ray: &Ray, max_t: f32, transparent: bool) -> bool {
+        let hit_distance = |primitive| match primitive {
+            Primitive::Cube(index) if (self.cubes[index].material.transparency > 0.0) == transparent => {
+                self.cubes[index].hit_distance(ray)
+            }
+            Primitive::Sphere(index) if (self.spheres[index].material.transparency > 0.0) == transparent => {
+                self.sphere_at(index, ray.time).hit_distance(ray)
             }
+            _ => None,
+        };
+
+        match self.accelerator {
+            Accelerator::Bvh => self.bvh.occluded(ray, max_t, hit_distance),
+            Accelerator::VoxelGrid => self.voxel_grid.occluded(ray, max_t, hit_distance),
         }
-        
-        for sphere in &self.spheres {
-            let hit = sphere.intersect(ray, self.time);
-            if hit.hit && hit.distance < closest_hit.distance {
-                closest_hit = hit;
+    }
+
+    // Transmittance along a shadow ray from its origin towards `light`,
+    // whose center is `distance` away
+    pub fn light_transmittance(&self, ray: &Ray, light: &Light, distance: f32) -> Vec3 {
+        let max_t = light.shadow_distance(&ray.origin, &ray.direction, distance);
+        let cloud_t = if light.above_clouds { f32::INFINITY } else { max_t };
+        self.transmittance(ray, max_t, cloud_t)
+    }
+
+    // Fraction of light that gets through along a shadow ray up to `max_t`.
+    // Opaque blockers stop it completely. Each transparent medium the ray
+    // crosses filters it once by its albedo and transparency, and by
+    // Beer-Lambert absorption over the distance travelled inside, which
+    // gives tinted shadows that deepen with the depth of the water. Faces
+    // between neighbouring blocks of the same material are passed through
+    // as one volume, and a ray that starts inside the water is treated as
+    // having entered it at its origin. The cloud layer counts up to
+    // `cloud_t`. Closest-hit queries only run when an any-hit query finds
+    // something transparent on the segment.
+    fn transmittance(&self, ray: &Ray, max_t: f32, cloud_t: f32) -> Vec3 {
+        if self.occluded(ray, max_t) {
+            return Vec3::zero();
+        }
+
+        let mut transmittance = Vec3::one() * self.clouds.shadow(ray, cloud_t, self.time + ray.time);
+        // Nothing transparent on the way either, so no layers to walk
+        if !self.any_hit(ray, max_t, true) {
+            return transmittance;
+        }
+
+        // Medium the ray is inside and where it entered it, and the medium
+        // it just left and where, in distance from the ray origin
+        let mut inside: Option<(Material, f32)> = None;
+        let mut left: Option<(Material, f32)> = None;
+        let mut segment = *ray;
+        let mut travelled = 0.0;
+
+        for _ in 0..MAX_SHADOW_LAYERS {
+            let hit = self.intersect(&segment);
+            if !hit.hit || travelled + hit.distance > max_t {
+                break;
             }
+            let t = travelled + hit.distance;
+
+            if segment.direction.dot(&hit.normal) > 0.0 {
+                // With no entry on record the ray either started inside this
+                // volume or stepped into it past the face it shares with the
+                // one it just left, which the same material continues
+                let entry = match (inside.take(), left.take()) {
+                    (Some((_, entry)), _) => entry,
+                    (None, Some((previous, exit))) => {
+                        if previous.material_type != hit.material.material_type {
+                            transmittance = transmittance * surface_filter(&previous);
+                        }
+                        exit
+                    }
+                    (None, None) => 0.0,
+                };
+                transmittance = hit.material.attenuate(transmittance, t - entry, Vec3::zero());
+                left = Some((hit.material, t));
+            } else {
+                // Entering right where the same material was left is the
+                // face between two blocks of one volume
+                left = match left {
+                    Some((previous, exit)) if previous.material_type == hit.material.material_type && t - exit < ADJACENT_FACE_GAP => None,
+                    Some((previous, _)) => {
+                        transmittance = transmittance * surface_filter(&previous);
+                        None
+                    }
+                    None => None,
+                };
+                inside = Some((hit.material, t));
+            }
+
+            segment.origin = hit.point + segment.direction * 0.001;
+            travelled = t + 0.001;
         }
-        
-        closest_hit
+
+        // Out of layers or past the last face: account for whatever volume
+        // the ray is still in up to `max_t`, and the last one it left
+        if let Some((material, entry)) = inside {
+            transmittance = material.attenuate(transmittance, (max_t - entry).min(MAX_SHADOW_MEDIUM_DISTANCE), Vec3::zero());
+        }
+        if let Some((previous, _)) = left {
+            transmittance = transmittance * surface_filter(&previous);
+        }
+        transmittance
+    }
+
+    // Unshadowed light reaching particles suspended in water or glass
+    pub fn scattered_light(&self) -> Vec3 {
+        self.ambient_light + sunlight(&self.sun_position, self.sun_intensity)
     }
 
-    pub fn get_sky_color(&self, _direction: &Vec3) -> Vec3 {
-        let day_sky = Vec3::new(0.5, 0.7, 1.0);      
+    pub fn get_sky_color(&self, direction: &Vec3, include_sun_disk: bool) -> Vec3 {
         let night_sky = Vec3::new(0.08, 0.08, 0.18); 
+
+        let daylight = daylight(self.sky.sun_direction.y);
+
+        // The moon hides the stars behind it, even on its dark side
+        let moon = moon_disk(direction, &self.moon_direction, self.moon_phase);
+        let stars = if moon == Vec3::zero() { starfield(direction) } else { Vec3::zero() };
+        let night = night_sky + stars + moon;
+
+        let sky = self.sky.radiance(direction, include_sun_disk) * daylight + night * (1.0 - daylight);
+        let lights = [
+            (self.sky.sun_direction, sunlight(&self.sun_position, self.sun_intensity)),
+            (self.moon_direction, moonlight(&self.moon_direction, self.moon_phase)),
+        ];
+        self.clouds.composite(direction, sky, &lights, self.ambient_light, self.time)
+    }
+}
+
+// Tint a transparent surface gives the light it lets through
+fn surface_filter(material: &Material) -> Vec3 {
+    material.albedo * material.transparency
+}
+
+// Sun position and intensity of the day-night cycle at `time`
+fn sun_at(time: f32) -> (Vec3, f32) {
+    let day_cycle = time * DAY_CYCLE_RATE;
+    let position = Vec3::new(
+        day_cycle.cos() * 15.0,
+        day_cycle.sin() * 10.0,
+        10.0,
+    );
+    let intensity = (day_cycle.sin().max(0.0) * 0.8 + 0.2).max(0.1);
+    (position, intensity)
+}
+
+// How much of the daylight sky and sunlight remains at a sun elevation
+// (the y of its direction), fading smoothly out just below the horizon
+fn daylight(elevation: f32) -> f32 {
+    let daylight = ((elevation + 0.1) / 0.15).clamp(0.0, 1.0);
+    daylight * daylight * (3.0 - 2.0 * daylight)
+}
+
+// Sun color at `intensity`, faded out with the daylight sky as the sun
+// sinks below the horizon so it stops lighting the world from underneath
+fn sunlight(sun_position: &Vec3, intensity: f32) -> Vec3 {
+    SUN_COLOR * (intensity * daylight(sun_position.normalize().y))
+}
+
+// The moon orbits opposite the sun and runs through its phases once every
+// `LUNAR_PERIOD`, starting full
+fn moon_at(time: f32) -> (Vec3, f32) {
+    let (sun_position, _) = sun_at(time);
+    let phase = (time / LUNAR_PERIOD + 0.5).fract();
+    ((sun_position * -1.0).normalize(), phase)
+}
+
+// Cool moonlight, scaled by the lit part of the disk and faded out while the
+// moon is below the horizon
+fn moonlight(moon_direction: &Vec3, phase: f32) -> Vec3 {
+    let visibility = (moon_direction.y / 0.1).clamp(0.0, 1.0);
+    MOONLIGHT_COLOR * (MOONLIGHT_INTENSITY * moon_illumination(phase) * visibility)
+}
+
+// Center and radius of campfire particle `particle_index` at `time`
+fn fire_particle_at(particle_index: usize, time: f32) -> (Vec3, f32) {
+    let campfire_x = -6.0;
+    let campfire_z = -6.0;
+
+    if particle_index < 8 {
+        // Outer fire rays - move them in arcs and upward
+        let base_angle = (particle_index as f32) * 0.785; 
+        let time_offset = particle_index as f32 * 0.3; 
+        let wave_time = time * 3.0 + time_offset;
+        
+        // Create swaying motion like torch flames
+        let sway_x = (wave_time * 1.2).sin() * 0.2;
+        let sway_z = (wave_time * 0.8).cos() * 0.15;
+        let rise = (wave_time * 2.0).sin().abs() * 0.5 + 0.3; 
+        
+        // Reset particle position if it gets too high 
+        let height = if rise > 1.0 { 0.1 } else { rise };
+        
+        let distance = 0.2 + (wave_time * 0.5).sin().abs() * 0.3;
+        
+        let center = Vec3::new(
+            campfire_x + (base_angle + sway_x).cos() * distance,
+            height,
+            campfire_z + (base_angle + sway_z).sin() * distance
+        );
+        
+        // Vary radius for flickering effect
+        (center, 0.06 + (wave_time * 4.0).sin().abs() * 0.04)
+    } else {
+        // Central flame particles - vertical motion with wobble
+        let central_index = particle_index - 8;
+        let wobble_time = time * 4.0 + central_index as f32 * 0.5;
+        
+        let wobble_x = (wobble_time * 1.5).sin() * 0.08;
+        let wobble_z = (wobble_time * 1.8).cos() * 0.06;
+        let flicker_height = (wobble_time * 2.5).sin().abs() * 0.2 + central_index as f32 * 0.12;
+        
+        // Reset if too high
+        let height = if flicker_height > 0.8 { 0.0 } else { flicker_height };
         
-        day_sky.lerp(&night_sky, 1.0 - self.sun_intensity)
+        let center = Vec3::new(
+            campfire_x + wobble_x,
+            height,
+            campfire_z + wobble_z
+        );
+        
+        (center, 0.08 + (0.8 - height) * 0.05 + (wobble_time * 6.0).sin().abs() * 0.02)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Closest hit over every cube and sphere, without an accelerator
+    fn linear_scan(scene: &Scene, ray: &Ray) -> HitInfo {
+        let time = scene.time + ray.time;
+        let mut closest = HitInfo::miss();
+        let cubes = scene.cubes.iter().map(|cube| cube.intersect(ray, time));
+        let spheres = (0..scene.spheres.len()).map(|index| scene.sphere_at(index, ray.time).intersect(ray, time));
+        for hit in cubes.chain(spheres) {
+            if hit.hit && hit.distance < closest.distance {
+                closest = hit;
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn accelerators_match_linear_scan() {
+        let mut scene = Scene::new();
+        let origins = [Vec3::new(0.0, 5.0, 10.0), Vec3::new(-8.0, 3.0, -8.0), Vec3::new(2.0, 12.0, 1.0)];
+        let targets = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(-3.0, 2.0, 0.0),
+            Vec3::new(-6.0, 0.2, -6.0),
+            Vec3::new(-6.0, 3.0, 6.0),
+            Vec3::new(6.0, -0.7, 8.0),
+            Vec3::new(20.0, 30.0, 5.0),
+        ];
+
+        for accelerator in [Accelerator::Bvh, Accelerator::VoxelGrid] {
+            scene.accelerator = accelerator;
+            for origin in &origins {
+                for target in &targets {
+                    let ray = Ray::new(*origin, *target - *origin);
+                    let expected = linear_scan(&scene, &ray);
+                    let hit = scene.intersect(&ray);
+                    assert_eq!(hit.hit, expected.hit, "{:?} from {:?} to {:?}", accelerator, origin, target);
+                    if expected.hit {
+                        assert!((hit.distance - expected.distance).abs() < 1e-4, "{:?} from {:?} to {:?}", accelerator, origin, target);
+                    }
+                }
+            }
+        }
+    }
+
+    // Scene holding only `cubes`, with the accelerators rebuilt around them
+    fn scene_with_cubes(cubes: Vec<Cube>) -> Scene {
+        let mut scene = Scene::new();
+        scene.cubes = cubes;
+        scene.spheres.clear();
+        scene.fire_particle_start_index = 0;
+        scene.bvh = Bvh::build(&scene.cubes, &scene.spheres);
+        scene.voxel_grid = VoxelGrid::build(&scene.cubes, &scene.spheres, VOXEL_CELL_SIZE);
+        scene
+    }
+
+    #[test]
+    fn adjacent_water_blocks_transmit_like_one_block() {
+        let ray = Ray::new(Vec3::new(-2.0, 0.1, 0.2), Vec3::new(1.0, 0.0, 0.0));
+        let two = scene_with_cubes(vec![
+            Cube::new(Vec3::new(0.0, 0.0, 0.0), Vec3::one(), Material::water()),
+            Cube::new(Vec3::new(1.0, 0.0, 0.0), Vec3::one(), Material::water()),
+        ]);
+        let one = scene_with_cubes(vec![
+            Cube::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0), Material::water()),
+        ]);
+
+        let layered = two.transmittance(&ray, 5.0, 5.0);
+        let single = one.transmittance(&ray, 5.0, 5.0);
+        assert!(single.x < 1.0 && single.x > 0.0, "{:?}", single);
+        assert!((layered - single).length() < 1e-4, "{:?} vs {:?}", layered, single);
+    }
+}
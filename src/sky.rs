@@ -0,0 +1,199 @@
+use crate::math::Vec3;
+use crate::sampling::hash;
+use std::f32::consts::PI;
+
+// Brings Preetham luminance (kcd/m^2) into the range of the scene lights
+const SKY_EXPOSURE: f32 = 0.06;
+// Angular radius of the sun disk, in radians
+const SUN_DISK_RADIUS: f32 = 0.03;
+const SUN_DISK_RADIANCE: f32 = 20.0;
+// Per-channel extinction used to redden the sun disk near the horizon
+const SUN_EXTINCTION: Vec3 = Vec3 { x: 0.05, y: 0.11, z: 0.28 };
+
+// Angular size of one starfield cell and the fraction of cells with a star
+const STAR_CELL_SIZE: f32 = 0.02;
+const STAR_DENSITY: f32 = 0.12;
+const MOON_DISK_RADIUS: f32 = 0.06;
+const MOON_COLOR: Vec3 = Vec3 { x: 0.9, y: 0.9, z: 0.85 };
+// Faint light on the dark side of the moon, reflected from the Earth
+const EARTHSHINE: f32 = 0.04;
+
+// Preetham, Shirley and Smits' analytic daylight model. Each channel of the
+// xyY color is a Perez distribution scaled by its value at the zenith.
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    pub sun_direction: Vec3,
+    perez_luminance: [f32; 5],
+    perez_x: [f32; 5],
+    perez_y: [f32; 5],
+    zenith: Vec3,
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        let sun_direction = sun_direction.normalize();
+        let t = turbidity;
+        // The model is only valid with the sun above the horizon
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos().min(PI / 2.0 - 0.01);
+
+        let perez_luminance = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_y = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let (t2, th, th2, th3) = (t * t, theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t2 * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y = t2 * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        Sky {
+            sun_direction,
+            perez_luminance,
+            perez_x,
+            perez_y,
+            zenith: Vec3::new(zenith_x, zenith_y, zenith_luminance),
+        }
+    }
+
+    // Linear RGB radiance seen along `direction`, with the sun disk if
+    // `include_sun_disk`. Paths that already sample the sun as a light
+    // leave it out. Directions below the horizon get the horizon color.
+    pub fn radiance(&self, direction: &Vec3, include_sun_disk: bool) -> Vec3 {
+        let direction = direction.normalize();
+        let cos_theta = direction.y.max(0.001);
+        let cos_gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let theta_s = self.sun_direction.y.clamp(0.0, 1.0).acos().min(PI / 2.0 - 0.01);
+
+        let channel = |coefficients: &[f32; 5], zenith: f32| {
+            zenith * perez(coefficients, cos_theta, gamma, cos_gamma)
+                / perez(coefficients, 1.0, theta_s, theta_s.cos())
+        };
+        let x = channel(&self.perez_x, self.zenith.x);
+        let y = channel(&self.perez_y, self.zenith.y);
+        let luminance = channel(&self.perez_luminance, self.zenith.z) * SKY_EXPOSURE;
+
+        let mut color = xyy_to_rgb(x, y, luminance);
+        if include_sun_disk && gamma < SUN_DISK_RADIUS && self.sun_direction.y > 0.0 {
+            color = color + self.sun_transmittance() * SUN_DISK_RADIANCE;
+        }
+        color
+    }
+
+    // Fraction of sunlight left after crossing the atmosphere; blue is lost
+    // first, which turns the sun red as it nears the horizon
+    pub fn sun_transmittance(&self) -> Vec3 {
+        let elevation = self.sun_direction.y.clamp(0.0, 1.0).asin().to_degrees();
+        // Kasten and Young's relative air mass
+        let zenith_angle = 90.0 - elevation;
+        let air_mass = 1.0 / (zenith_angle.to_radians().cos() + 0.50572 * (96.07995 - zenith_angle).powf(-1.6364));
+        Vec3::new(
+            (-SUN_EXTINCTION.x * air_mass).exp(),
+            (-SUN_EXTINCTION.y * air_mass).exp(),
+            (-SUN_EXTINCTION.z * air_mass).exp(),
+        )
+    }
+}
+
+fn perez(coefficients: &[f32; 5], cos_theta: f32, gamma: f32, cos_gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::zero();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vec3::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+// Procedural stars: the sky is split into cells of equal angle and a hashed
+// fraction of them hold one small star at a hashed position and brightness
+pub fn starfield(direction: &Vec3) -> Vec3 {
+    let direction = direction.normalize();
+    if direction.y <= 0.0 {
+        return Vec3::zero();
+    }
+
+    let phi = direction.z.atan2(direction.x) + PI;
+    let theta = direction.y.clamp(-1.0, 1.0).acos();
+    let (u, v) = (phi / STAR_CELL_SIZE, theta / STAR_CELL_SIZE);
+    let (cell_u, cell_v) = (u.floor() as u32, v.floor() as u32);
+
+    let seed = hash(cell_u ^ hash(cell_v));
+    if (seed & 0xffff) as f32 / 65535.0 > STAR_DENSITY {
+        return Vec3::zero();
+    }
+
+    let star_u = ((seed >> 16) & 0xff) as f32 / 255.0 * 0.6 + 0.2;
+    let star_v = ((seed >> 24) & 0xff) as f32 / 255.0 * 0.6 + 0.2;
+    let (du, dv) = (u.fract() - star_u, v.fract() - star_v);
+    let falloff = (1.0 - (du * du + dv * dv).sqrt() / 0.18).max(0.0);
+    if falloff <= 0.0 {
+        return Vec3::zero();
+    }
+
+    let brightness = (hash(seed) & 0xff) as f32 / 255.0;
+    // Hotter stars are bluer
+    let tint = Vec3::new(1.0, 0.9, 0.8).lerp(&Vec3::new(0.75, 0.85, 1.0), brightness);
+    tint * (falloff * falloff * (0.3 + brightness * brightness * 2.0))
+}
+
+// Fraction of the moon's face that is lit; `phase` runs from 0 (new) through
+// 0.5 (full) back to 1
+pub fn moon_illumination(phase: f32) -> f32 {
+    (1.0 - (2.0 * PI * phase).cos()) * 0.5
+}
+
+// The moon disk centered on `moon_direction`, shaded as a sphere lit from
+// the side that matches `phase`
+pub fn moon_disk(direction: &Vec3, moon_direction: &Vec3, phase: f32) -> Vec3 {
+    let direction = direction.normalize();
+    let moon_direction = moon_direction.normalize();
+    let cos_angle = direction.dot(&moon_direction);
+    if cos_angle < MOON_DISK_RADIUS.cos() {
+        return Vec3::zero();
+    }
+
+    // Position on the disk in units of its radius, lifted onto the sphere
+    let offset = (direction - moon_direction * cos_angle) / MOON_DISK_RADIUS.sin();
+    let to_viewer = moon_direction * -1.0;
+    let normal = offset + to_viewer * (1.0 - offset.length_squared()).max(0.0).sqrt();
+
+    // Sunlight arrives from behind the moon at new moon and from the
+    // viewer's side at full moon
+    let side = Vec3::new(0.0, 1.0, 0.0).cross(&moon_direction).normalize();
+    let angle = PI - 2.0 * PI * phase;
+    let to_sun = to_viewer * angle.cos() + side * angle.sin();
+
+    MOON_COLOR * (normal.dot(&to_sun).max(0.0) + EARTHSHINE)
+}
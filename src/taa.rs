@@ -0,0 +1,108 @@
+use crate::math::Vec3;
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use rayon::prelude::*;
+
+// Sky pixels have no depth; they are reprojected as if this far away
+const SKY_DISTANCE: f32 = 1.0e4;
+
+// Temporal anti-aliasing for the moving camera. Each pixel finds where its
+// primary hit was on screen last frame, fetches the history there and
+// blends a little of the new frame in. The history is first clamped to the
+// range of colors around the pixel this frame, so disoccluded or changed
+// surfaces drop their stale history instead of ghosting.
+#[derive(Debug, Clone)]
+pub struct Taa {
+    pub enabled: bool,
+    // Weight of the current frame in the blend
+    pub blend: f32,
+    history: Vec<Vec3>,
+    previous_camera: Option<Camera>,
+}
+
+impl Taa {
+    pub fn new() -> Self {
+        Taa {
+            enabled: false,
+            blend: 0.1,
+            history: Vec::new(),
+            previous_camera: None,
+        }
+    }
+
+    // Resolves `framebuffer.display` against the history. While the view
+    // holds still the framebuffer already accumulates, so the frame passes
+    // through and only becomes the new history.
+    pub fn apply(&mut self, framebuffer: &mut Framebuffer, camera: &Camera) {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let previous_camera = match self.previous_camera.replace(camera.clone()) {
+            Some(previous) if self.enabled && self.history.len() == width * height => previous,
+            _ => {
+                self.history = framebuffer.display.clone();
+                return;
+            }
+        };
+        if framebuffer.accumulated_frames > 1 {
+            self.history.copy_from_slice(&framebuffer.display);
+            return;
+        }
+
+        let current = &framebuffer.display;
+        let history = &self.history;
+        let resolved: Vec<Vec3> = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                let color = current[index];
+
+                let ray = camera.get_ray(x as f32 + 0.5, height as f32 - (y as f32 + 0.5), width as f32, height as f32);
+                let point = ray.at(framebuffer.depth[index].min(SKY_DISTANCE));
+                let (previous_x, previous_y) = match previous_camera.project(&point, width as f32, height as f32) {
+                    Some((px, py)) => (px, height as f32 - py),
+                    None => return color,
+                };
+                if previous_x < 0.0 || previous_y < 0.0 || previous_x >= width as f32 || previous_y >= height as f32 {
+                    return color;
+                }
+
+                let (low, high) = neighbourhood_bounds(current, width, height, x, y);
+                let previous = sample_bilinear(history, width, height, previous_x, previous_y);
+                let previous = Vec3::new(
+                    previous.x.clamp(low.x, high.x),
+                    previous.y.clamp(low.y, high.y),
+                    previous.z.clamp(low.z, high.z),
+                );
+                previous.lerp(&color, self.blend)
+            })
+            .collect();
+
+        framebuffer.display.copy_from_slice(&resolved);
+        self.history = resolved;
+    }
+}
+
+// Per-channel minimum and maximum over the 3x3 pixels around `x`, `y`
+fn neighbourhood_bounds(image: &[Vec3], width: usize, height: usize, x: usize, y: usize) -> (Vec3, Vec3) {
+    let mut low = Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut high = Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+        for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+            let color = image[ny * width + nx];
+            low = Vec3::new(low.x.min(color.x), low.y.min(color.y), low.z.min(color.z));
+            high = Vec3::new(high.x.max(color.x), high.y.max(color.y), high.z.max(color.z));
+        }
+    }
+    (low, high)
+}
+
+// `image` at pixel position `x`, `y` measured from the top-left corner
+fn sample_bilinear(image: &[Vec3], width: usize, height: usize, x: f32, y: f32) -> Vec3 {
+    let (u, v) = ((x - 0.5).max(0.0), (y - 0.5).max(0.0));
+    let (x0, y0) = ((u as usize).min(width - 1), (v as usize).min(height - 1));
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (u - x0 as f32, v - y0 as f32);
+
+    let top = image[y0 * width + x0].lerp(&image[y0 * width + x1], fx);
+    let bottom = image[y1 * width + x0].lerp(&image[y1 * width + x1], fx);
+    top.lerp(&bottom, fy)
+}
@@ -0,0 +1,109 @@
+use crate::math::Vec3;
+
+// Maps exposed HDR radiance to linear display values in [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapOperator {
+    Reinhard,
+    // Reinhard that reaches white at `white_point` instead of infinity
+    ExtendedReinhard { white_point: f32 },
+    // Narkowicz's curve fit of the ACES filmic reference transform
+    AcesFilmic,
+    AgX,
+}
+
+impl ToneMapOperator {
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let color = Vec3::new(color.x.max(0.0), color.y.max(0.0), color.z.max(0.0));
+        let mapped = match *self {
+            ToneMapOperator::Reinhard => per_channel(color, |x| x / (1.0 + x)),
+            ToneMapOperator::ExtendedReinhard { white_point } => {
+                let white_squared = (white_point * white_point).max(1e-4);
+                per_channel(color, |x| x * (1.0 + x / white_squared) / (1.0 + x))
+            }
+            ToneMapOperator::AcesFilmic => per_channel(color * 0.6, |x| {
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }),
+            ToneMapOperator::AgX => agx(color),
+        };
+        per_channel(mapped, |x| x.clamp(0.0, 1.0))
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            ToneMapOperator::Reinhard => ToneMapOperator::ExtendedReinhard { white_point: 4.0 },
+            ToneMapOperator::ExtendedReinhard { .. } => ToneMapOperator::AcesFilmic,
+            ToneMapOperator::AcesFilmic => ToneMapOperator::AgX,
+            ToneMapOperator::AgX => ToneMapOperator::Reinhard,
+        }
+    }
+}
+
+pub fn luminance(color: &Vec3) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+fn per_channel(color: Vec3, f: impl Fn(f32) -> f32) -> Vec3 {
+    Vec3::new(f(color.x), f(color.y), f(color.z))
+}
+
+// Minimal AgX: squeeze the primaries towards white, encode in log2 between
+// fixed stops, apply the sigmoid contrast curve and undo both
+fn agx(color: Vec3) -> Vec3 {
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let inset = Vec3::new(
+        0.8424791 * color.x + 0.0784336 * color.y + 0.0792237 * color.z,
+        0.0423282 * color.x + 0.8784686 * color.y + 0.0791661 * color.z,
+        0.0423757 * color.x + 0.0784336 * color.y + 0.879143 * color.z,
+    );
+
+    let curve = per_channel(inset, |x| {
+        let x = ((x.max(1e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0.0, 1.0);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+
+    let outset = Vec3::new(
+        1.196879 * curve.x - 0.0980209 * curve.y - 0.0990297 * curve.z,
+        -0.0528969 * curve.x + 1.1519031 * curve.y - 0.0989612 * curve.z,
+        -0.0529716 * curve.x - 0.0980435 * curve.y + 1.1510737 * curve.z,
+    );
+
+    // The curve targets a 2.2 display; bring it back to linear
+    per_channel(outset, |x| x.max(0.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapOperator; 4] = [
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ExtendedReinhard { white_point: 4.0 },
+        ToneMapOperator::AcesFilmic,
+        ToneMapOperator::AgX,
+    ];
+
+    #[test]
+    fn operators_are_monotonic_and_bounded() {
+        for operator in OPERATORS {
+            let mut previous = 0.0;
+            for step in 0..=400 {
+                let x = step as f32 * 0.05;
+                let mapped = operator.apply(Vec3::one() * x);
+                for channel in [mapped.x, mapped.y, mapped.z] {
+                    assert!((0.0..=1.0).contains(&channel), "{:?} maps {} to {}", operator, x, channel);
+                }
+                assert!(mapped.y >= previous - 1e-6, "{:?} decreases at {}", operator, x);
+                previous = mapped.y;
+            }
+        }
+    }
+
+    #[test]
+    fn reinhard_never_reaches_white() {
+        assert!(ToneMapOperator::Reinhard.apply(Vec3::one() * 1000.0).x < 1.0);
+    }
+}
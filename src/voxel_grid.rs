@@ -0,0 +1,230 @@
+use crate::math::{Vec3, Ray};
+use crate::scene::{Cube, Sphere, HitInfo};
+use crate::bvh::{Aabb, Primitive};
+
+// Rod-like boxes (logs, torches...) whose two smaller sides are below this
+// fraction of a cell are not grid blocks and go into the fallback list.
+// Slabs such as the grass and water layers still qualify.
+const MIN_BLOCK_FILL: f32 = 0.5;
+const BOUNDARY_EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    origin: Vec3,
+    cell_size: f32,
+    dims: [usize; 3],
+    // Cell contents stored back to back, `cell_start[i]..cell_start[i + 1]`
+    // indexes into `cell_cubes` for cell `i`
+    cell_start: Vec<usize>,
+    cell_cubes: Vec<usize>,
+    fallback: Vec<Primitive>,
+}
+
+impl VoxelGrid {
+    pub fn build(cubes: &[Cube], spheres: &[Sphere], cell_size: f32) -> Self {
+        let mut fallback: Vec<Primitive> = Vec::new();
+        let mut grid_cubes: Vec<usize> = Vec::new();
+
+        for (index, cube) in cubes.iter().enumerate() {
+            let mut extents = [cube.size.x, cube.size.y, cube.size.z];
+            extents.sort_by(|a, b| a.total_cmp(b));
+            if extents[1] >= cell_size * MIN_BLOCK_FILL {
+                grid_cubes.push(index);
+            } else {
+                fallback.push(Primitive::Cube(index));
+            }
+        }
+        fallback.extend((0..spheres.len()).map(Primitive::Sphere));
+
+        let bounds = grid_cubes
+            .iter()
+            .fold(Aabb::empty(), |acc, &index| acc.union(&Aabb::from_cube(&cubes[index])));
+
+        if grid_cubes.is_empty() {
+            return VoxelGrid {
+                origin: Vec3::zero(),
+                cell_size,
+                dims: [0, 0, 0],
+                cell_start: vec![0],
+                cell_cubes: Vec::new(),
+                fallback,
+            };
+        }
+
+        let origin = bounds.min;
+        let extent = bounds.max - bounds.min;
+        let dims = [
+            ((extent.x / cell_size).ceil() as usize).max(1),
+            ((extent.y / cell_size).ceil() as usize).max(1),
+            ((extent.z / cell_size).ceil() as usize).max(1),
+        ];
+
+        let mut cells: Vec<Vec<usize>> = vec![Vec::new(); dims[0] * dims[1] * dims[2]];
+        for &index in &grid_cubes {
+            let cube_bounds = Aabb::from_cube(&cubes[index]);
+            // Shrink slightly so blocks that only touch a cell boundary stay out of it
+            let lo = cell_coords(&(cube_bounds.min - origin + Vec3::one() * BOUNDARY_EPSILON), cell_size, &dims);
+            let hi = cell_coords(&(cube_bounds.max - origin - Vec3::one() * BOUNDARY_EPSILON), cell_size, &dims);
+            for z in lo[2]..=hi[2] {
+                for y in lo[1]..=hi[1] {
+                    for x in lo[0]..=hi[0] {
+                        cells[(z * dims[1] + y) * dims[0] + x].push(index);
+                    }
+                }
+            }
+        }
+
+        let mut cell_start = Vec::with_capacity(cells.len() + 1);
+        let mut cell_cubes = Vec::new();
+        for cell in &cells {
+            cell_start.push(cell_cubes.len());
+            cell_cubes.extend_from_slice(cell);
+        }
+        cell_start.push(cell_cubes.len());
+
+        VoxelGrid {
+            origin,
+            cell_size,
+            dims,
+            cell_start,
+            cell_cubes,
+            fallback,
+        }
+    }
+
+    pub fn intersect<F>(&self, ray: &Ray, mut intersect_primitive: F) -> HitInfo
+    where
+        F: FnMut(Primitive) -> HitInfo,
+    {
+        let mut closest_hit = HitInfo::miss();
+        let mut closest_primitive = None;
+
+        let mut consider = |primitive: Primitive, closest_hit: &mut HitInfo, closest_primitive: &mut Option<Primitive>| {
+            let hit = intersect_primitive(primitive);
+            // Ties on shared faces go to the primitive declared first
+            let closer = hit.distance < closest_hit.distance
+                || (hit.distance == closest_hit.distance && Some(primitive) < *closest_primitive);
+            if hit.hit && closer {
+                *closest_hit = hit;
+                *closest_primitive = Some(primitive);
+            }
+        };
+
+        for primitive in &self.fallback {
+            consider(*primitive, &mut closest_hit, &mut closest_primitive);
+        }
+
+        self.walk_cells(ray, closest_hit.distance, |cubes, cell_exit| {
+            for &index in cubes {
+                consider(Primitive::Cube(index), &mut closest_hit, &mut closest_primitive);
+            }
+
+            // Anything hit before leaving this cell cannot be beaten further
+            // along. Hits right on the boundary may tie with a face in the
+            // next cell, so those keep walking.
+            closest_hit.distance + BOUNDARY_EPSILON < cell_exit
+        });
+
+        closest_hit
+    }
+
+    pub fn occluded<F>(&self, ray: &Ray, max_t: f32, mut hit_distance: F) -> bool
+    where
+        F: FnMut(Primitive) -> Option<f32>,
+    {
+        let blocks = |t: Option<f32>| matches!(t, Some(t) if t <= max_t);
+
+        if self.fallback.iter().any(|primitive| blocks(hit_distance(*primitive))) {
+            return true;
+        }
+
+        let mut occluded = false;
+        self.walk_cells(ray, max_t, |cubes, _| {
+            occluded = cubes.iter().any(|&index| blocks(hit_distance(Primitive::Cube(index))));
+            occluded
+        });
+
+        occluded
+    }
+
+    // Amanatides-Woo 3D-DDA. Calls `visit` with the cubes of every cell the
+    // ray crosses before `t_limit`, in order, together with the distance at
+    // which the ray leaves that cell. Stops early when `visit` returns true.
+    fn walk_cells<F>(&self, ray: &Ray, t_limit: f32, mut visit: F)
+    where
+        F: FnMut(&[usize], f32) -> bool,
+    {
+        if self.cell_cubes.is_empty() {
+            return;
+        }
+
+        let inv_dir = Vec3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let grid_bounds = Aabb {
+            min: self.origin,
+            max: self.origin + Vec3::new(
+                self.dims[0] as f32 * self.cell_size,
+                self.dims[1] as f32 * self.cell_size,
+                self.dims[2] as f32 * self.cell_size,
+            ),
+        };
+
+        let t_enter = match grid_bounds.hit(ray, &inv_dir, t_limit) {
+            Some(t) => t,
+            None => return,
+        };
+
+        let entry = ray.at(t_enter) - self.origin;
+        let mut cell = cell_coords(&entry, self.cell_size, &self.dims);
+        let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let inv = [inv_dir.x, inv_dir.y, inv_dir.z];
+        let origin = [ray.origin.x - self.origin.x, ray.origin.y - self.origin.y, ray.origin.z - self.origin.z];
+
+        let mut step = [0i32; 3];
+        let mut t_max = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                t_delta[axis] = self.cell_size * inv[axis];
+                t_max[axis] = ((cell[axis] + 1) as f32 * self.cell_size - origin[axis]) * inv[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                t_delta[axis] = -self.cell_size * inv[axis];
+                t_max[axis] = (cell[axis] as f32 * self.cell_size - origin[axis]) * inv[axis];
+            }
+        }
+
+        loop {
+            let axis = if t_max[0] < t_max[1] {
+                if t_max[0] < t_max[2] { 0 } else { 2 }
+            } else if t_max[1] < t_max[2] {
+                1
+            } else {
+                2
+            };
+
+            let cell_index = (cell[2] * self.dims[1] + cell[1]) * self.dims[0] + cell[0];
+            let cubes = &self.cell_cubes[self.cell_start[cell_index]..self.cell_start[cell_index + 1]];
+            if visit(cubes, t_max[axis]) || t_max[axis] > t_limit {
+                return;
+            }
+
+            let next = cell[axis] as i32 + step[axis];
+            if next < 0 || next >= self.dims[axis] as i32 {
+                return;
+            }
+            cell[axis] = next as usize;
+            t_max[axis] += t_delta[axis];
+        }
+    }
+}
+
+// Cell containing `local`, an offset from the grid origin, clamped to the grid
+pub(crate) fn cell_coords(local: &Vec3, cell_size: f32, dims: &[usize; 3]) -> [usize; 3] {
+    let clamp = |v: f32, dim: usize| ((v / cell_size).floor().max(0.0) as usize).min(dim - 1);
+    [
+        clamp(local.x, dims[0]),
+        clamp(local.y, dims[1]),
+        clamp(local.z, dims[2]),
+    ]
+}